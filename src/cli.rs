@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Write};
+
+use crate::database;
+use crate::error::AppError;
+use crate::services::room::archive::{self, ArchiveFormat};
+use crate::services::room::model::Room;
+
+const USAGE: &str = "\
+Usage: actix-webrtc-messenger [COMMAND]
+
+Starts the server when no command is given.
+Commands work on the database file at MESSENGER_DATABASE_PATH.
+
+Commands:
+  export <ROOM NAME> [ndjson|text]  Write the history of a room to stdout
+  import <FILE>                     Restore a room from an NDJSON archive";
+
+pub fn run(arguments: &[String]) -> io::Result<()> {
+    // An in-memory database starts out empty and is gone once the command is done
+    if database::is_in_memory() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "MESSENGER_DATABASE_PATH must point to a database file",
+        ));
+    }
+
+    let result = match arguments {
+        [command, room_name] if command == "export" => export(room_name, ArchiveFormat::default()),
+        [command, room_name, format] if command == "export" => {
+            format.parse().and_then(|format| export(room_name, format))
+        }
+        [command, path] if command == "import" => import(path),
+        _ => {
+            eprintln!("{USAGE}");
+
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unknown command",
+            ));
+        }
+    };
+
+    result.map_err(|error| io::Error::other(error.get_safe_message()))
+}
+
+fn export(room_name: &str, format: ArchiveFormat) -> Result<(), AppError> {
+    let room = Room::find_by_name(room_name)?;
+    let mut stdout = io::stdout().lock();
+
+    for line in archive::export(room, format)? {
+        stdout
+            .write_all(line?.as_bytes())
+            .map_err(|error| AppError::new(500, None, format!("IO error: {error}"), None))?;
+    }
+
+    Ok(())
+}
+
+fn import(path: &str) -> Result<(), AppError> {
+    let archive = File::open(path)
+        .map_err(|error| AppError::new(500, None, format!("IO error: {error}"), None))?;
+    let room = archive::import(BufReader::new(archive))?;
+
+    info!("Imported room {} ({})", room.name, room.id);
+
+    Ok(())
+}
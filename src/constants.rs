@@ -6,11 +6,36 @@ pub const WEB_SOCKET_CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
 pub const WEB_RTC_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 pub const WEB_RTC_CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
 pub const WEB_RTC_DATA_CHANNEL_BUFFER_SIZE: usize = 1024 * 4;
+pub const WEB_RTC_EPHEMERAL_DATA_CHANNEL_LABEL: &str = "ephemeral";
+pub const WEB_RTC_TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+pub const WEB_RTC_TYPING_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+pub const WEB_RTC_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// `<opcode>[:<payload type>]=<requests>/<seconds>`, a new peer connection costs the most
+pub const WEB_SOCKET_RATE_LIMITS: &str = "1=20/10,1:10=5/60";
+pub const WEB_RTC_RATE_LIMITS: &str = "1=50/10,1:10=10/10";
+pub const RETENTION_PURGE_INTERVAL: Duration = Duration::from_secs(60);
 
 // Models
 pub const MESSAGE_CONTENT_MIN_LENGTH: usize = 1;
 pub const MESSAGE_CONTENT_MAX_LENGTH: usize = 1024;
+pub const MESSAGE_PAGE_DEFAULT_LIMIT: usize = 50;
+pub const MESSAGE_PAGE_MAX_LIMIT: usize = 100;
+pub const MESSAGE_SEARCH_TOKEN_MAX_LENGTH: usize = 32;
+pub const MESSAGE_DELETION_REASON_MAX_LENGTH: usize = 256;
+pub const MESSAGE_REACTION_EMOJI_MIN_LENGTH: usize = 1;
+pub const MESSAGE_REACTION_EMOJI_MAX_LENGTH: usize = 16;
+pub const MESSAGE_FILTER_MAX_RULES: usize = 16;
+pub const MESSAGE_FILTER_MAX_PATTERNS: usize = 256;
+/// Bytes a compiled filter regex may take, rooms keep their compiled filters in memory
+pub const MESSAGE_FILTER_REGEX_SIZE_LIMIT: usize = 1024 * 1024;
 pub const ROOM_NAME_MIN_LENGTH: usize = 3;
 pub const ROOM_NAME_MAX_LENGTH: usize = 32;
+pub const ROOM_PASSWORD_MIN_LENGTH: usize = 4;
+pub const ROOM_PASSWORD_MAX_LENGTH: usize = 128;
+pub const ROOM_MESSAGE_MAX_AGE: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+pub const ROOM_INVITE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+pub const ROOM_SLOW_MODE_MAX_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
 pub const USER_USERNAME_MIN_LENGTH: usize = 3;
 pub const USER_USERNAME_MAX_LENGTH: usize = 32;
+pub const USER_STATUS_TEXT_MAX_LENGTH: usize = 128;
+pub const USER_MUTE_MAX_DURATION: Duration = Duration::from_secs(28 * 24 * 60 * 60);
@@ -0,0 +1,125 @@
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::database::backends::sqlite::SqliteDatabase;
+use crate::database::repository::{DirectMessageRepository, Order};
+use crate::error::AppError;
+use crate::services::direct_message::model::{DirectChannel, DirectMessage};
+
+const CHANNEL_COLUMNS: &str = "id, room_id, first_user_id, second_user_id";
+const COLUMNS: &str = "id, channel_id, author_id, recipient_id, content, created_at";
+
+impl DirectMessageRepository for SqliteDatabase {
+    fn insert_channel(&self, channel: &DirectChannel) -> Result<(), AppError> {
+        self.connection().execute(
+            &format!("INSERT INTO direct_channels ({CHANNEL_COLUMNS}) VALUES (?1, ?2, ?3, ?4)"),
+            params![
+                channel.id,
+                channel.room_id,
+                channel.first_user_id,
+                channel.second_user_id,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn find_channel_by_user_ids(
+        &self,
+        first_user_id: &i64,
+        second_user_id: &i64,
+    ) -> Result<Option<DirectChannel>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!(
+                    "SELECT {CHANNEL_COLUMNS} FROM direct_channels
+                    WHERE first_user_id = ?1 AND second_user_id = ?2"
+                ),
+                params![first_user_id, second_user_id],
+                channel_from_row,
+            )
+            .optional()?)
+    }
+
+    fn insert(&self, message: &DirectMessage) -> Result<(), AppError> {
+        self.connection().execute(
+            &format!("INSERT INTO direct_messages ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)"),
+            params![
+                message.id,
+                message.channel_id,
+                message.author_id,
+                message.recipient_id,
+                message.content,
+                message.created_at,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn find_by_channel_id(
+        &self,
+        channel_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<DirectMessage>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {COLUMNS} FROM direct_messages
+            WHERE channel_id = ?1 AND (?2 IS NULL OR id > ?2) AND (?3 IS NULL OR id < ?3)
+            ORDER BY id {}
+            LIMIT ?4",
+            match order {
+                Order::Asc => "ASC",
+                Order::Desc => "DESC",
+            },
+        ))?;
+        let messages = statement
+            .query_map(params![channel_id, after, before, limit], from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(messages)
+    }
+
+    fn delete_orphaned(&self) -> Result<usize, AppError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        let deleted = transaction.execute(
+            "DELETE FROM direct_messages WHERE channel_id IN (
+                SELECT id FROM direct_channels WHERE room_id NOT IN (SELECT id FROM rooms)
+            )",
+            [],
+        )?;
+        transaction.execute(
+            "DELETE FROM direct_channels WHERE room_id NOT IN (SELECT id FROM rooms)",
+            [],
+        )?;
+
+        transaction.commit()?;
+
+        Ok(deleted)
+    }
+}
+
+fn channel_from_row(row: &Row) -> rusqlite::Result<DirectChannel> {
+    Ok(DirectChannel {
+        id: row.get("id")?,
+        room_id: row.get("room_id")?,
+        first_user_id: row.get("first_user_id")?,
+        second_user_id: row.get("second_user_id")?,
+    })
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<DirectMessage> {
+    Ok(DirectMessage {
+        id: row.get("id")?,
+        channel_id: row.get("channel_id")?,
+        author_id: row.get("author_id")?,
+        recipient_id: row.get("recipient_id")?,
+        content: row.get("content")?,
+        created_at: row.get("created_at")?,
+    })
+}
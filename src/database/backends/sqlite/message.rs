@@ -0,0 +1,400 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+
+use crate::database::backends::sqlite::SqliteDatabase;
+use crate::database::repository::{MessageRepository, Order};
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::model::{Message, MessageMention, MessageReaction, MessageRevision};
+use crate::services::message::search;
+
+const COLUMNS: &str = "id, author_id, room_id, reply_to_id, thread_id, content, mentions, \
+    created_at, edited_at, deleted_at, deleted_by, deletion_reason";
+
+impl MessageRepository for SqliteDatabase {
+    fn insert(&self, message: &Message) -> Result<(), AppError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        insert(&transaction, message)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn update(&self, message: &Message, revision: &MessageRevision) -> Result<(), AppError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        let updated = transaction.execute(
+            "UPDATE messages SET content = ?2, mentions = ?3, edited_at = ?4 WHERE id = ?1",
+            params![
+                message.id,
+                message.content,
+                encode_mentions(&message.mentions),
+                message.edited_at,
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        transaction.execute(
+            "INSERT INTO message_revisions (id, message_id, content, created_at)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                revision.id,
+                revision.message_id,
+                revision.content,
+                revision.created_at,
+            ],
+        )?;
+        transaction.execute(
+            "DELETE FROM message_tokens WHERE message_id = ?1",
+            params![message.id],
+        )?;
+        insert_tokens(&transaction, message)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn delete(&self, message: &Message) -> Result<(), AppError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        let updated = transaction.execute(
+            "UPDATE messages
+            SET content = ?2, mentions = ?3, deleted_at = ?4, deleted_by = ?5, deletion_reason = ?6
+            WHERE id = ?1",
+            params![
+                message.id,
+                message.content,
+                encode_mentions(&message.mentions),
+                message.deleted_at,
+                message.deleted_by,
+                message.deletion_reason,
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        transaction.execute(
+            "DELETE FROM message_tokens WHERE message_id = ?1",
+            params![message.id],
+        )?;
+        transaction.execute(
+            "DELETE FROM message_revisions WHERE message_id = ?1",
+            params![message.id],
+        )?;
+        transaction.execute(
+            "DELETE FROM message_reactions WHERE message_id = ?1",
+            params![message.id],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_by_id(&self, id: &i64) -> Result<Option<Message>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM messages WHERE id = ?1"),
+                params![id],
+                from_row,
+            )
+            .optional()?)
+    }
+
+    fn find_revisions_by_message_id(&self, id: &i64) -> Result<Vec<MessageRevision>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, message_id, content, created_at FROM message_revisions
+            WHERE message_id = ?1
+            ORDER BY id",
+        )?;
+        let revisions = statement
+            .query_map(params![id], |row| {
+                Ok(MessageRevision {
+                    id: row.get("id")?,
+                    message_id: row.get("message_id")?,
+                    content: row.get("content")?,
+                    created_at: row.get("created_at")?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(revisions)
+    }
+
+    fn insert_reaction(&self, reaction: &MessageReaction) -> Result<(), AppError> {
+        self.connection().execute(
+            "INSERT INTO message_reactions (id, message_id, user_id, emoji)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                reaction.id,
+                reaction.message_id,
+                reaction.user_id,
+                reaction.emoji,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn delete_reaction(
+        &self,
+        message_id: &i64,
+        user_id: &i64,
+        emoji: &str,
+    ) -> Result<bool, AppError> {
+        let deleted = self.connection().execute(
+            "DELETE FROM message_reactions WHERE message_id = ?1 AND user_id = ?2 AND emoji = ?3",
+            params![message_id, user_id, emoji],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    fn find_reactions_by_message_id(&self, id: &i64) -> Result<Vec<MessageReaction>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, message_id, user_id, emoji FROM message_reactions
+            WHERE message_id = ?1
+            ORDER BY id",
+        )?;
+        let reactions = statement
+            .query_map(params![id], |row| {
+                Ok(MessageReaction {
+                    id: row.get("id")?,
+                    message_id: row.get("message_id")?,
+                    user_id: row.get("user_id")?,
+                    emoji: row.get("emoji")?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(reactions)
+    }
+
+    fn find_by_room_id(
+        &self,
+        room_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<Message>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {COLUMNS} FROM messages
+            WHERE room_id = ?1 AND (?2 IS NULL OR id > ?2) AND (?3 IS NULL OR id < ?3)
+            ORDER BY id {}
+            LIMIT ?4",
+            match order {
+                Order::Asc => "ASC",
+                Order::Desc => "DESC",
+            },
+        ))?;
+        let messages = statement
+            .query_map(params![room_id, after, before, limit], from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(messages)
+    }
+
+    fn find_by_thread_id(
+        &self,
+        thread_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<Message>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {COLUMNS} FROM messages
+            WHERE thread_id = ?1 AND (?2 IS NULL OR id > ?2) AND (?3 IS NULL OR id < ?3)
+            ORDER BY id {}
+            LIMIT ?4",
+            match order {
+                Order::Asc => "ASC",
+                Order::Desc => "DESC",
+            },
+        ))?;
+        let messages = statement
+            .query_map(params![thread_id, after, before, limit], from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(messages)
+    }
+
+    fn count_by_thread_ids(&self, thread_ids: &[i64]) -> Result<HashMap<i64, usize>, AppError> {
+        if thread_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT thread_id, COUNT(*) FROM messages
+            WHERE thread_id IN ({}) AND deleted_at IS NULL
+            GROUP BY thread_id",
+            vec!["?"; thread_ids.len()].join(", "),
+        ))?;
+        let counts = statement
+            .query_map(params_from_iter(thread_ids), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(counts)
+    }
+
+    fn count_by_room_id(
+        &self,
+        room_id: &i64,
+        after: Option<i64>,
+        excluded_author_id: &i64,
+    ) -> Result<usize, AppError> {
+        Ok(self.connection().query_row(
+            "SELECT COUNT(*) FROM messages
+            WHERE room_id = ?1 AND (?2 IS NULL OR id > ?2) AND author_id != ?3
+                AND deleted_at IS NULL",
+            params![room_id, after, excluded_author_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn find_ids_by_token_prefix(
+        &self,
+        room_id: &i64,
+        prefix: &str,
+    ) -> Result<HashSet<i64>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT token, message_id FROM message_tokens
+            WHERE room_id = ?1 AND token >= ?2
+            ORDER BY token",
+        )?;
+        let mut rows = statement.query(params![room_id, prefix])?;
+        let mut ids = HashSet::new();
+
+        while let Some(row) = rows.next()? {
+            if !row.get::<_, String>("token")?.starts_with(prefix) {
+                break;
+            }
+
+            ids.insert(row.get("message_id")?);
+        }
+
+        Ok(ids)
+    }
+
+    fn delete_expired_by_room_id(
+        &self,
+        room_id: &i64,
+        expired_at: Option<i64>,
+        max_count: Option<u64>,
+    ) -> Result<usize, AppError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let mut deleted = 0;
+
+        if let Some(expired_at) = expired_at {
+            deleted += transaction.execute(
+                "DELETE FROM messages WHERE room_id = ?1 AND created_at < ?2",
+                params![room_id, expired_at],
+            )?;
+        }
+
+        if let Some(max_count) = max_count {
+            deleted += transaction.execute(
+                "DELETE FROM messages
+                WHERE room_id = ?1 AND id NOT IN (
+                    SELECT id FROM messages WHERE room_id = ?1 ORDER BY id DESC LIMIT ?2
+                )",
+                params![room_id, max_count],
+            )?;
+        }
+
+        transaction.commit()?;
+
+        Ok(deleted)
+    }
+
+    fn delete_orphaned(&self) -> Result<usize, AppError> {
+        let deleted = self.connection().execute(
+            "DELETE FROM messages WHERE room_id NOT IN (SELECT id FROM rooms)",
+            [],
+        )?;
+
+        Ok(deleted)
+    }
+}
+
+/// Inserts a message with its search tokens, which are deleted along with it
+pub fn insert(connection: &Connection, message: &Message) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!(
+            "INSERT INTO messages ({COLUMNS})
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"
+        ),
+        params![
+            message.id,
+            message.author_id,
+            message.room_id,
+            message.reply_to_id,
+            message.thread_id,
+            message.content,
+            encode_mentions(&message.mentions),
+            message.created_at,
+            message.edited_at,
+            message.deleted_at,
+            message.deleted_by,
+            message.deletion_reason,
+        ],
+    )?;
+
+    insert_tokens(connection, message)
+}
+
+fn insert_tokens(connection: &Connection, message: &Message) -> rusqlite::Result<()> {
+    for token in search::tokenize(&message.content) {
+        connection.execute(
+            "INSERT INTO message_tokens (token, room_id, message_id) VALUES (?1, ?2, ?3)",
+            params![token, message.room_id, message.id],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Mentions are only looked at along with their message, so they are kept as a JSON array
+fn encode_mentions(mentions: &[MessageMention]) -> String {
+    serde_json::to_string(mentions).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn decode_mentions(mentions: &str) -> Vec<MessageMention> {
+    serde_json::from_str(mentions).unwrap_or_default()
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: row.get("id")?,
+        author_id: row.get("author_id")?,
+        room_id: row.get("room_id")?,
+        reply_to_id: row.get("reply_to_id")?,
+        thread_id: row.get("thread_id")?,
+        content: row.get("content")?,
+        mentions: decode_mentions(&row.get::<_, String>("mentions")?),
+        created_at: row.get("created_at")?,
+        edited_at: row.get("edited_at")?,
+        deleted_at: row.get("deleted_at")?,
+        deleted_by: row.get("deleted_by")?,
+        deletion_reason: row.get("deletion_reason")?,
+    })
+}
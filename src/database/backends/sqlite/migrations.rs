@@ -0,0 +1,225 @@
+use rusqlite::{ffi, Connection};
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    sql: &'static str,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create tables",
+        sql: "
+            CREATE TABLE sessions (
+                id INTEGER PRIMARY KEY,
+                token TEXT NOT NULL UNIQUE
+            );
+
+            CREATE TABLE rooms (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                mode INTEGER NOT NULL,
+                message_max_age INTEGER,
+                message_max_count INTEGER,
+                active_connection_ids TEXT NOT NULL
+            );
+
+            CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL,
+                room_id INTEGER NOT NULL,
+                session_id INTEGER NOT NULL,
+                active_connection_ids TEXT NOT NULL,
+                UNIQUE (room_id, username)
+            );
+            CREATE INDEX users_session_id ON users (session_id);
+
+            CREATE TABLE messages (
+                id INTEGER PRIMARY KEY,
+                author_id INTEGER NOT NULL,
+                room_id INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX messages_room_id ON messages (room_id, id);
+            CREATE INDEX messages_room_id_created_at ON messages (room_id, created_at);
+
+            CREATE TABLE message_tokens (
+                token TEXT NOT NULL,
+                room_id INTEGER NOT NULL,
+                message_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+                PRIMARY KEY (room_id, token, message_id)
+            ) WITHOUT ROWID;
+            CREATE INDEX message_tokens_message_id ON message_tokens (message_id);
+        ",
+    },
+    Migration {
+        version: 2,
+        description: "Add edited_at and revisions to messages",
+        sql: "
+            ALTER TABLE messages ADD COLUMN edited_at INTEGER;
+
+            CREATE TABLE message_revisions (
+                id INTEGER PRIMARY KEY,
+                message_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+                content TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX message_revisions_message_id ON message_revisions (message_id);
+        ",
+    },
+    Migration {
+        version: 3,
+        description: "Add deletion tombstones to messages",
+        sql: "
+            ALTER TABLE messages ADD COLUMN deleted_at INTEGER;
+            ALTER TABLE messages ADD COLUMN deleted_by INTEGER;
+            ALTER TABLE messages ADD COLUMN deletion_reason TEXT;
+        ",
+    },
+    Migration {
+        version: 4,
+        description: "Create message reactions",
+        sql: "
+            CREATE TABLE message_reactions (
+                id INTEGER PRIMARY KEY,
+                message_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+                user_id INTEGER NOT NULL,
+                emoji TEXT NOT NULL,
+                UNIQUE (message_id, user_id, emoji)
+            );
+        ",
+    },
+    Migration {
+        version: 5,
+        description: "Add replies and threads to messages",
+        sql: "
+            ALTER TABLE messages ADD COLUMN reply_to_id INTEGER;
+            ALTER TABLE messages ADD COLUMN thread_id INTEGER;
+            CREATE INDEX messages_thread_id ON messages (thread_id, id);
+        ",
+    },
+    Migration {
+        version: 6,
+        description: "Add mentions to messages",
+        sql: "ALTER TABLE messages ADD COLUMN mentions TEXT NOT NULL DEFAULT '[]';",
+    },
+    Migration {
+        version: 7,
+        description: "Add read positions to users",
+        sql: "ALTER TABLE users ADD COLUMN last_read_message_id INTEGER;",
+    },
+    Migration {
+        version: 8,
+        description: "Add statuses and idle connections to users",
+        sql: "
+            ALTER TABLE users ADD COLUMN status INTEGER NOT NULL DEFAULT 1;
+            ALTER TABLE users ADD COLUMN status_text TEXT;
+            ALTER TABLE users ADD COLUMN idle_connection_ids TEXT NOT NULL DEFAULT '[]';
+        ",
+    },
+    Migration {
+        version: 9,
+        description: "Create direct channels and messages",
+        sql: "
+            CREATE TABLE direct_channels (
+                id INTEGER PRIMARY KEY,
+                room_id INTEGER NOT NULL,
+                first_user_id INTEGER NOT NULL,
+                second_user_id INTEGER NOT NULL,
+                UNIQUE (first_user_id, second_user_id)
+            );
+            CREATE INDEX direct_channels_room_id ON direct_channels (room_id);
+
+            CREATE TABLE direct_messages (
+                id INTEGER PRIMARY KEY,
+                channel_id INTEGER NOT NULL REFERENCES direct_channels (id) ON DELETE CASCADE,
+                author_id INTEGER NOT NULL,
+                recipient_id INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX direct_messages_channel_id ON direct_messages (channel_id, id);
+        ",
+    },
+    Migration {
+        version: 10,
+        description: "Add access modes and invites to rooms",
+        sql: "
+            ALTER TABLE rooms ADD COLUMN access INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE rooms ADD COLUMN password_hash TEXT;
+
+            CREATE TABLE room_invites (
+                code TEXT PRIMARY KEY,
+                room_id INTEGER NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+                created_by INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL
+            );
+            CREATE INDEX room_invites_room_id ON room_invites (room_id);
+        ",
+    },
+    Migration {
+        version: 11,
+        description: "Add roles to users",
+        sql: "ALTER TABLE users ADD COLUMN role INTEGER NOT NULL DEFAULT 0;",
+    },
+    Migration {
+        version: 12,
+        description: "Add banned sessions to rooms",
+        sql: "ALTER TABLE rooms ADD COLUMN banned_session_ids TEXT NOT NULL DEFAULT '[]';",
+    },
+    Migration {
+        version: 13,
+        description: "Add mutes to users",
+        sql: "ALTER TABLE users ADD COLUMN muted_until INTEGER;",
+    },
+    Migration {
+        version: 14,
+        description: "Add slow mode to rooms",
+        sql: "
+            ALTER TABLE rooms ADD COLUMN slow_mode_interval INTEGER;
+            ALTER TABLE users ADD COLUMN last_message_at INTEGER;
+        ",
+    },
+    Migration {
+        version: 15,
+        description: "Add message filters to rooms",
+        sql: "ALTER TABLE rooms ADD COLUMN message_filters TEXT NOT NULL DEFAULT '[]';",
+    },
+];
+
+/// Brings the schema to the latest version, which is kept in `user_version`.
+///
+/// Fails on a database written by a newer version of the server.
+pub fn run(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: u32 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let latest = MIGRATIONS.last().map_or(0, |migration| migration.version);
+
+    if version > latest {
+        return Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_MISMATCH),
+            Some(format!(
+                "Database version {version} is newer than the latest known version {latest}"
+            )),
+        ));
+    }
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+    {
+        info!(
+            "Migrate database to version {}: {}",
+            migration.version, migration.description
+        );
+
+        let transaction = connection.transaction()?;
+
+        transaction.execute_batch(migration.sql)?;
+        transaction.pragma_update(None, "user_version", migration.version)?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
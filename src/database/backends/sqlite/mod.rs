@@ -0,0 +1,70 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use rusqlite::Connection;
+
+use crate::database::repository::{
+    Database, DirectMessageRepository, MessageRepository, RoomRepository, SessionRepository,
+    UserRepository,
+};
+
+mod direct_message;
+mod message;
+mod migrations;
+mod room;
+mod session;
+mod user;
+
+pub struct SqliteDatabase {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let mut connection = Connection::open(path)?;
+
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrations::run(&mut connection)?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        // A panic can't leave a statement half-done, so the connection is still usable
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Database for SqliteDatabase {
+    fn sessions(&self) -> &dyn SessionRepository {
+        self
+    }
+
+    fn rooms(&self) -> &dyn RoomRepository {
+        self
+    }
+
+    fn users(&self) -> &dyn UserRepository {
+        self
+    }
+
+    fn messages(&self) -> &dyn MessageRepository {
+        self
+    }
+
+    fn direct_messages(&self) -> &dyn DirectMessageRepository {
+        self
+    }
+}
+
+/// Connection IDs are only looked at as a whole, so they are kept as a JSON array
+fn encode_ids(ids: &[i64]) -> String {
+    serde_json::to_string(ids).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn decode_ids(ids: &str) -> Vec<i64> {
+    serde_json::from_str(ids).unwrap_or_default()
+}
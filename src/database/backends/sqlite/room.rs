@@ -0,0 +1,202 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::database::backends::sqlite::{decode_ids, encode_ids, message, user, SqliteDatabase};
+use crate::database::repository::RoomRepository;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::filter::MessageFilterRule;
+use crate::services::message::model::Message;
+use crate::services::room::model::{Room, RoomAccess, RoomInvite, RoomMode};
+use crate::services::user::model::User;
+
+const COLUMNS: &str = "id, name, mode, access, password_hash, message_max_age, message_max_count,
+    slow_mode_interval, message_filters, banned_session_ids, active_connection_ids";
+const INVITE_COLUMNS: &str = "code, room_id, created_by, expires_at";
+
+impl RoomRepository for SqliteDatabase {
+    fn insert(&self, room: &Room) -> Result<(), AppError> {
+        insert(&self.connection(), room)?;
+
+        Ok(())
+    }
+
+    fn find_all(&self) -> Result<Vec<Room>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!("SELECT {COLUMNS} FROM rooms"))?;
+        let rooms = statement
+            .query_map([], from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(rooms)
+    }
+
+    fn find_by_id(&self, id: &i64) -> Result<Option<Room>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM rooms WHERE id = ?1"),
+                params![id],
+                from_row,
+            )
+            .optional()?)
+    }
+
+    fn find_by_name(&self, name: &str) -> Result<Option<Room>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM rooms WHERE name = ?1"),
+                params![name],
+                from_row,
+            )
+            .optional()?)
+    }
+
+    fn update(&self, room: &Room) -> Result<(), AppError> {
+        let updated = self.connection().execute(
+            "UPDATE rooms
+            SET name = ?2, mode = ?3, access = ?4, password_hash = ?5, message_max_age = ?6,
+                message_max_count = ?7, slow_mode_interval = ?8, message_filters = ?9,
+                banned_session_ids = ?10, active_connection_ids = ?11
+            WHERE id = ?1",
+            params![
+                room.id,
+                room.name,
+                room.mode as u8,
+                room.access as u8,
+                room.password_hash,
+                room.message_max_age,
+                room.message_max_count,
+                room.slow_mode_interval,
+                encode_message_filters(&room.message_filters),
+                encode_ids(&room.banned_session_ids),
+                encode_ids(&room.active_connection_ids),
+            ],
+        )?;
+
+        match updated {
+            0 => Err(AppErrorTemplate::NotFound(None).into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete(&self, id: &i64) -> Result<(), AppError> {
+        self.connection()
+            .execute("DELETE FROM rooms WHERE id = ?1", params![id])?;
+
+        Ok(())
+    }
+
+    fn import(&self, room: &Room, users: &[User], messages: &[Message]) -> Result<(), AppError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        insert(&transaction, room)?;
+
+        for user in users {
+            user::insert(&transaction, user)?;
+        }
+
+        for message in messages {
+            message::insert(&transaction, message)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn insert_invite(&self, invite: &RoomInvite) -> Result<(), AppError> {
+        self.connection().execute(
+            &format!("INSERT INTO room_invites ({INVITE_COLUMNS}) VALUES (?1, ?2, ?3, ?4)"),
+            params![
+                invite.code,
+                invite.room_id,
+                invite.created_by,
+                invite.expires_at,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn find_invite_by_code(&self, code: &str) -> Result<Option<RoomInvite>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!("SELECT {INVITE_COLUMNS} FROM room_invites WHERE code = ?1"),
+                params![code],
+                invite_from_row,
+            )
+            .optional()?)
+    }
+
+    fn delete_invite(&self, code: &str) -> Result<(), AppError> {
+        self.connection()
+            .execute("DELETE FROM room_invites WHERE code = ?1", params![code])?;
+
+        Ok(())
+    }
+}
+
+fn insert(connection: &Connection, room: &Room) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!(
+            "INSERT INTO rooms ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"
+        ),
+        params![
+            room.id,
+            room.name,
+            room.mode as u8,
+            room.access as u8,
+            room.password_hash,
+            room.message_max_age,
+            room.message_max_count,
+            room.slow_mode_interval,
+            encode_message_filters(&room.message_filters),
+            encode_ids(&room.banned_session_ids),
+            encode_ids(&room.active_connection_ids),
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<Room> {
+    Ok(Room {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        mode: match row.get::<_, u8>("mode")? {
+            mode if mode == RoomMode::Persistent as u8 => RoomMode::Persistent,
+            _ => RoomMode::Ephemeral,
+        },
+        access: match row.get::<_, u8>("access")? {
+            access if access == RoomAccess::Password as u8 => RoomAccess::Password,
+            access if access == RoomAccess::InviteOnly as u8 => RoomAccess::InviteOnly,
+            _ => RoomAccess::Open,
+        },
+        password_hash: row.get("password_hash")?,
+        message_max_age: row.get("message_max_age")?,
+        message_max_count: row.get("message_max_count")?,
+        slow_mode_interval: row.get("slow_mode_interval")?,
+        message_filters: decode_message_filters(&row.get::<_, String>("message_filters")?),
+        banned_session_ids: decode_ids(&row.get::<_, String>("banned_session_ids")?),
+        active_connection_ids: decode_ids(&row.get::<_, String>("active_connection_ids")?),
+    })
+}
+
+fn encode_message_filters(message_filters: &[MessageFilterRule]) -> String {
+    serde_json::to_string(message_filters).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn decode_message_filters(message_filters: &str) -> Vec<MessageFilterRule> {
+    serde_json::from_str(message_filters).unwrap_or_default()
+}
+
+fn invite_from_row(row: &Row) -> rusqlite::Result<RoomInvite> {
+    Ok(RoomInvite {
+        code: row.get("code")?,
+        room_id: row.get("room_id")?,
+        created_by: row.get("created_by")?,
+        expires_at: row.get("expires_at")?,
+    })
+}
@@ -0,0 +1,35 @@
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::database::backends::sqlite::SqliteDatabase;
+use crate::database::repository::SessionRepository;
+use crate::error::AppError;
+use crate::services::session::model::Session;
+
+impl SessionRepository for SqliteDatabase {
+    fn insert(&self, session: &Session) -> Result<(), AppError> {
+        self.connection().execute(
+            "INSERT INTO sessions (id, token) VALUES (?1, ?2)",
+            params![session.id, session.token],
+        )?;
+
+        Ok(())
+    }
+
+    fn find_by_token(&self, token: &str) -> Result<Option<Session>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                "SELECT id, token FROM sessions WHERE token = ?1",
+                params![token],
+                from_row,
+            )
+            .optional()?)
+    }
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<Session> {
+    Ok(Session {
+        id: row.get("id")?,
+        token: row.get("token")?,
+    })
+}
@@ -0,0 +1,180 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::database::backends::sqlite::{decode_ids, encode_ids, SqliteDatabase};
+use crate::database::repository::UserRepository;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::user::model::{User, UserRole, UserStatus};
+
+const COLUMNS: &str = "id, username, room_id, session_id, role, last_read_message_id, status,
+    status_text, muted_until, last_message_at, active_connection_ids, idle_connection_ids";
+
+impl UserRepository for SqliteDatabase {
+    fn insert(&self, user: &User) -> Result<(), AppError> {
+        insert(&self.connection(), user)?;
+
+        Ok(())
+    }
+
+    fn find_all(&self) -> Result<Vec<User>, AppError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!("SELECT {COLUMNS} FROM users"))?;
+        let users = statement
+            .query_map([], from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(users)
+    }
+
+    fn find_by_id(&self, id: &i64) -> Result<Option<User>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM users WHERE id = ?1"),
+                params![id],
+                from_row,
+            )
+            .optional()?)
+    }
+
+    fn find_all_by_room_id(&self, room_id: &i64) -> Result<Vec<User>, AppError> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare(&format!("SELECT {COLUMNS} FROM users WHERE room_id = ?1"))?;
+        let users = statement
+            .query_map(params![room_id], from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(users)
+    }
+
+    fn find_by_username_and_room_id(
+        &self,
+        username: &str,
+        room_id: &i64,
+    ) -> Result<Option<User>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM users WHERE username = ?1 AND room_id = ?2"),
+                params![username, room_id],
+                from_row,
+            )
+            .optional()?)
+    }
+
+    fn find_by_session_id_and_room_id(
+        &self,
+        session_id: &i64,
+        room_id: &i64,
+    ) -> Result<Option<User>, AppError> {
+        Ok(self
+            .connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM users WHERE session_id = ?1 AND room_id = ?2"),
+                params![session_id, room_id],
+                from_row,
+            )
+            .optional()?)
+    }
+
+    fn update(&self, user: &User) -> Result<(), AppError> {
+        let updated = self.connection().execute(
+            "UPDATE users
+            SET username = ?2, room_id = ?3, session_id = ?4, role = ?5, last_read_message_id = ?6,
+                status = ?7, status_text = ?8, muted_until = ?9, last_message_at = ?10,
+                active_connection_ids = ?11, idle_connection_ids = ?12
+            WHERE id = ?1",
+            params![
+                user.id,
+                user.username,
+                user.room_id,
+                user.session_id,
+                user.role as u8,
+                user.last_read_message_id,
+                user.status as u8,
+                user.status_text,
+                user.muted_until,
+                user.last_message_at,
+                encode_ids(&user.active_connection_ids),
+                encode_ids(&user.idle_connection_ids),
+            ],
+        )?;
+
+        match updated {
+            0 => Err(AppErrorTemplate::NotFound(None).into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn update_last_message_at(
+        &self,
+        id: &i64,
+        last_message_at: i64,
+        since: Option<i64>,
+    ) -> Result<bool, AppError> {
+        let updated = self.connection().execute(
+            "UPDATE users SET last_message_at = ?2
+            WHERE id = ?1 AND (?3 IS NULL OR last_message_at IS NULL OR last_message_at <= ?3)",
+            params![id, last_message_at, since],
+        )?;
+
+        Ok(updated != 0)
+    }
+
+    fn delete_by_room_id(&self, room_id: &i64) -> Result<(), AppError> {
+        self.connection()
+            .execute("DELETE FROM users WHERE room_id = ?1", params![room_id])?;
+
+        Ok(())
+    }
+}
+
+pub fn insert(connection: &Connection, user: &User) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!(
+            "INSERT INTO users ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"
+        ),
+        params![
+            user.id,
+            user.username,
+            user.room_id,
+            user.session_id,
+            user.role as u8,
+            user.last_read_message_id,
+            user.status as u8,
+            user.status_text,
+            user.muted_until,
+            user.last_message_at,
+            encode_ids(&user.active_connection_ids),
+            encode_ids(&user.idle_connection_ids),
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get("id")?,
+        username: row.get("username")?,
+        room_id: row.get("room_id")?,
+        session_id: row.get("session_id")?,
+        role: match row.get::<_, u8>("role")? {
+            role if role == UserRole::Moderator as u8 => UserRole::Moderator,
+            role if role == UserRole::Owner as u8 => UserRole::Owner,
+            _ => UserRole::Member,
+        },
+        last_read_message_id: row.get("last_read_message_id")?,
+        status: match row.get::<_, u8>("status")? {
+            status if status == UserStatus::Idle as u8 => UserStatus::Idle,
+            status if status == UserStatus::Away as u8 => UserStatus::Away,
+            status if status == UserStatus::DoNotDisturb as u8 => UserStatus::DoNotDisturb,
+            _ => UserStatus::Online,
+        },
+        status_text: row.get("status_text")?,
+        muted_until: row.get("muted_until")?,
+        last_message_at: row.get("last_message_at")?,
+        active_connection_ids: decode_ids(&row.get::<_, String>("active_connection_ids")?),
+        idle_connection_ids: decode_ids(&row.get::<_, String>("idle_connection_ids")?),
+    })
+}
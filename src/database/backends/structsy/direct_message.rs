@@ -0,0 +1,111 @@
+use std::ops::{Bound, RangeBounds};
+
+use structsy::derive::queries;
+use structsy::StructsyTx;
+
+use crate::database::backends::structsy::{room, StructsyDatabase};
+use crate::database::repository::{DirectMessageRepository, Order};
+use crate::error::AppError;
+use crate::services::direct_message::model::{DirectChannel, DirectMessage};
+
+#[queries(DirectChannel)]
+trait DirectChannelQueries {
+    fn filter_by_first_user_id(self, first_user_id: i64) -> Self;
+    fn filter_by_second_user_id(self, second_user_id: i64) -> Self;
+}
+
+#[queries(DirectMessage)]
+trait DirectMessageQueries {
+    fn filter_by_channel_id(self, channel_id: i64) -> Self;
+    fn filter_by_id_range<R: RangeBounds<i64>>(self, id: R) -> Self;
+    fn order_by_id(self, id: structsy::Order) -> Self;
+}
+
+impl DirectMessageRepository for StructsyDatabase {
+    fn insert_channel(&self, channel: &DirectChannel) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(channel)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_channel_by_user_ids(
+        &self,
+        first_user_id: &i64,
+        second_user_id: &i64,
+    ) -> Result<Option<DirectChannel>, AppError> {
+        Ok(self
+            .structsy
+            .query::<DirectChannel>()
+            .filter_by_first_user_id(*first_user_id)
+            .filter_by_second_user_id(*second_user_id)
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn insert(&self, message: &DirectMessage) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(message)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_by_channel_id(
+        &self,
+        channel_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<DirectMessage>, AppError> {
+        let range = (
+            after.map_or(Bound::Unbounded, Bound::Excluded),
+            before.map_or(Bound::Unbounded, Bound::Excluded),
+        );
+
+        Ok(self
+            .structsy
+            .query::<DirectMessage>()
+            .filter_by_channel_id(*channel_id)
+            .filter_by_id_range(range)
+            .order_by_id(match order {
+                Order::Asc => structsy::Order::Asc,
+                Order::Desc => structsy::Order::Desc,
+            })
+            .into_iter()
+            .take(limit)
+            .map(|data| data.1)
+            .collect())
+    }
+
+    fn delete_orphaned(&self) -> Result<usize, AppError> {
+        let mut transaction = self.structsy.begin()?;
+        let mut deleted = 0;
+
+        for (channel_ref, channel) in self.structsy.scan::<DirectChannel>()? {
+            if room::exists(&self.structsy, channel.room_id)? {
+                continue;
+            }
+
+            for (message_ref, _) in self
+                .structsy
+                .query::<DirectMessage>()
+                .filter_by_channel_id(channel.id)
+            {
+                transaction.delete(&message_ref)?;
+                deleted += 1;
+            }
+
+            transaction.delete(&channel_ref)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(deleted)
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Bound, RangeBounds};
+
+use structsy::derive::{queries, Persistent};
+use structsy::{OwnedSytx, SRes, Structsy, StructsyTx};
+
+use crate::database::backends::structsy::{room, StructsyDatabase};
+use crate::database::repository::{MessageRepository, Order};
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::model::{Message, MessageReaction, MessageRevision};
+use crate::services::message::search;
+
+#[queries(Message)]
+trait MessageQueries {
+    fn filter_by_id(self, id: i64) -> Self;
+    fn filter_by_room_id(self, room_id: i64) -> Self;
+    fn filter_by_thread_id(self, thread_id: Option<i64>) -> Self;
+    fn filter_by_id_range<R: RangeBounds<i64>>(self, id: R) -> Self;
+    fn filter_by_created_at_range<R: RangeBounds<i64>>(self, created_at: R) -> Self;
+    fn order_by_id(self, id: structsy::Order) -> Self;
+}
+
+#[queries(MessageRevision)]
+trait MessageRevisionQueries {
+    fn filter_by_message_id(self, message_id: i64) -> Self;
+    fn order_by_id(self, id: structsy::Order) -> Self;
+}
+
+#[queries(MessageReaction)]
+trait MessageReactionQueries {
+    fn filter_by_message_id(self, message_id: i64) -> Self;
+    fn order_by_id(self, id: structsy::Order) -> Self;
+}
+
+#[queries(MessageToken)]
+trait MessageTokenQueries {
+    fn filter_by_token_range<R: RangeBounds<String>>(self, token: R) -> Self;
+    fn filter_by_room_id(self, room_id: i64) -> Self;
+    fn filter_by_message_id(self, message_id: i64) -> Self;
+    fn order_by_token(self, token: structsy::Order) -> Self;
+}
+
+/// Entry of the search index, one per token of a message
+#[derive(Debug, Persistent)]
+pub struct MessageToken {
+    #[index(mode = "cluster")]
+    pub token: String,
+    pub room_id: i64,
+    #[index(mode = "cluster")]
+    pub message_id: i64,
+}
+
+impl MessageToken {
+    pub fn tokenize(message: &Message) -> Vec<Self> {
+        search::tokenize(&message.content)
+            .into_iter()
+            .map(|token| Self {
+                token,
+                room_id: message.room_id,
+                message_id: message.id,
+            })
+            .collect()
+    }
+}
+
+/// Deletes the records that belong to a message: its search tokens, revisions and reactions
+fn delete_dependents(structsy: &Structsy, transaction: &mut OwnedSytx, id: &i64) -> SRes<()> {
+    for (token_ref, _) in structsy.query::<MessageToken>().filter_by_message_id(*id) {
+        transaction.delete(&token_ref)?;
+    }
+
+    for (revision_ref, _) in structsy
+        .query::<MessageRevision>()
+        .filter_by_message_id(*id)
+    {
+        transaction.delete(&revision_ref)?;
+    }
+
+    for (reaction_ref, _) in structsy
+        .query::<MessageReaction>()
+        .filter_by_message_id(*id)
+    {
+        transaction.delete(&reaction_ref)?;
+    }
+
+    Ok(())
+}
+
+impl MessageRepository for StructsyDatabase {
+    fn insert(&self, message: &Message) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(message)?;
+
+        for token in MessageToken::tokenize(message) {
+            transaction.insert(&token)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn update(&self, message: &Message, revision: &MessageRevision) -> Result<(), AppError> {
+        let Some((message_ref, _)) = self
+            .structsy
+            .query::<Message>()
+            .filter_by_id(message.id)
+            .into_iter()
+            .next()
+        else {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        };
+
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.update(&message_ref, message)?;
+        transaction.insert(revision)?;
+
+        for (token_ref, _) in self
+            .structsy
+            .query::<MessageToken>()
+            .filter_by_message_id(message.id)
+        {
+            transaction.delete(&token_ref)?;
+        }
+
+        for token in MessageToken::tokenize(message) {
+            transaction.insert(&token)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn delete(&self, message: &Message) -> Result<(), AppError> {
+        let Some((message_ref, _)) = self
+            .structsy
+            .query::<Message>()
+            .filter_by_id(message.id)
+            .into_iter()
+            .next()
+        else {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        };
+
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.update(&message_ref, message)?;
+        delete_dependents(&self.structsy, &mut transaction, &message.id)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_by_id(&self, id: &i64) -> Result<Option<Message>, AppError> {
+        Ok(self
+            .structsy
+            .query::<Message>()
+            .filter_by_id(*id)
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn find_revisions_by_message_id(&self, id: &i64) -> Result<Vec<MessageRevision>, AppError> {
+        Ok(self
+            .structsy
+            .query::<MessageRevision>()
+            .filter_by_message_id(*id)
+            .order_by_id(structsy::Order::Asc)
+            .into_iter()
+            .map(|data| data.1)
+            .collect())
+    }
+
+    fn insert_reaction(&self, reaction: &MessageReaction) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(reaction)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn delete_reaction(
+        &self,
+        message_id: &i64,
+        user_id: &i64,
+        emoji: &str,
+    ) -> Result<bool, AppError> {
+        let Some((reaction_ref, _)) = self
+            .structsy
+            .query::<MessageReaction>()
+            .filter_by_message_id(*message_id)
+            .into_iter()
+            .find(|(_, reaction)| &reaction.user_id == user_id && reaction.emoji == emoji)
+        else {
+            return Ok(false);
+        };
+
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.delete(&reaction_ref)?;
+        transaction.commit()?;
+
+        Ok(true)
+    }
+
+    fn find_reactions_by_message_id(&self, id: &i64) -> Result<Vec<MessageReaction>, AppError> {
+        Ok(self
+            .structsy
+            .query::<MessageReaction>()
+            .filter_by_message_id(*id)
+            .order_by_id(structsy::Order::Asc)
+            .into_iter()
+            .map(|data| data.1)
+            .collect())
+    }
+
+    fn find_by_room_id(
+        &self,
+        room_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<Message>, AppError> {
+        let range = (
+            after.map_or(Bound::Unbounded, Bound::Excluded),
+            before.map_or(Bound::Unbounded, Bound::Excluded),
+        );
+
+        Ok(self
+            .structsy
+            .query::<Message>()
+            .filter_by_room_id(*room_id)
+            .filter_by_id_range(range)
+            .order_by_id(match order {
+                Order::Asc => structsy::Order::Asc,
+                Order::Desc => structsy::Order::Desc,
+            })
+            .into_iter()
+            .take(limit)
+            .map(|data| data.1)
+            .collect())
+    }
+
+    fn find_by_thread_id(
+        &self,
+        thread_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<Message>, AppError> {
+        let range = (
+            after.map_or(Bound::Unbounded, Bound::Excluded),
+            before.map_or(Bound::Unbounded, Bound::Excluded),
+        );
+
+        Ok(self
+            .structsy
+            .query::<Message>()
+            .filter_by_thread_id(Some(*thread_id))
+            .filter_by_id_range(range)
+            .order_by_id(match order {
+                Order::Asc => structsy::Order::Asc,
+                Order::Desc => structsy::Order::Desc,
+            })
+            .into_iter()
+            .take(limit)
+            .map(|data| data.1)
+            .collect())
+    }
+
+    fn count_by_thread_ids(&self, thread_ids: &[i64]) -> Result<HashMap<i64, usize>, AppError> {
+        let mut counts = HashMap::new();
+
+        // Replies are newer than the messages starting their threads,
+        // so only the messages after the oldest one are read
+        let Some(oldest_id) = thread_ids.iter().min() else {
+            return Ok(counts);
+        };
+
+        for (_, message) in self
+            .structsy
+            .query::<Message>()
+            .filter_by_id_range((Bound::Excluded(*oldest_id), Bound::Unbounded))
+        {
+            match message.thread_id {
+                Some(thread_id)
+                    if message.deleted_at.is_none() && thread_ids.contains(&thread_id) =>
+                {
+                    *counts.entry(thread_id).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(counts)
+    }
+
+    fn count_by_room_id(
+        &self,
+        room_id: &i64,
+        after: Option<i64>,
+        excluded_author_id: &i64,
+    ) -> Result<usize, AppError> {
+        // The room isn't indexed, so only the messages after the bound are read off the ID index
+        Ok(self
+            .structsy
+            .query::<Message>()
+            .filter_by_id_range((
+                after.map_or(Bound::Unbounded, Bound::Excluded),
+                Bound::Unbounded,
+            ))
+            .into_iter()
+            .filter(|(_, message)| {
+                &message.room_id == room_id
+                    && &message.author_id != excluded_author_id
+                    && message.deleted_at.is_none()
+            })
+            .count())
+    }
+
+    fn find_ids_by_token_prefix(
+        &self,
+        room_id: &i64,
+        prefix: &str,
+    ) -> Result<HashSet<i64>, AppError> {
+        Ok(self
+            .structsy
+            .query::<MessageToken>()
+            .filter_by_room_id(*room_id)
+            .filter_by_token_range(prefix.to_string()..)
+            .order_by_token(structsy::Order::Asc)
+            .into_iter()
+            .take_while(|(_, token)| token.token.starts_with(prefix))
+            .map(|(_, token)| token.message_id)
+            .collect())
+    }
+
+    fn delete_expired_by_room_id(
+        &self,
+        room_id: &i64,
+        expired_at: Option<i64>,
+        max_count: Option<u64>,
+    ) -> Result<usize, AppError> {
+        let mut transaction = self.structsy.begin()?;
+        let mut deleted = 0;
+
+        if let Some(expired_at) = expired_at {
+            for (message_ref, message) in self
+                .structsy
+                .query::<Message>()
+                .filter_by_room_id(*room_id)
+                .filter_by_created_at_range(..expired_at)
+            {
+                transaction.delete(&message_ref)?;
+                delete_dependents(&self.structsy, &mut transaction, &message.id)?;
+                deleted += 1;
+            }
+        }
+
+        if let Some(max_count) = max_count {
+            for (message_ref, message) in self
+                .structsy
+                .query::<Message>()
+                .filter_by_room_id(*room_id)
+                .order_by_id(structsy::Order::Desc)
+                .into_iter()
+                .skip(max_count as usize)
+                .filter(|(_, message)| {
+                    expired_at.is_none_or(|expired_at| message.created_at >= expired_at)
+                })
+            {
+                transaction.delete(&message_ref)?;
+                delete_dependents(&self.structsy, &mut transaction, &message.id)?;
+                deleted += 1;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(deleted)
+    }
+
+    fn delete_orphaned(&self) -> Result<usize, AppError> {
+        let mut transaction = self.structsy.begin()?;
+        let mut room_ids = HashMap::new();
+        let mut deleted = 0;
+
+        // A room is looked up after its message is read, so a room created meanwhile is seen
+        for (message_ref, message) in self.structsy.scan::<Message>()? {
+            let room_exists = match room_ids.entry(message.room_id) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    *entry.insert(room::exists(&self.structsy, message.room_id)?)
+                }
+            };
+
+            if !room_exists {
+                transaction.delete(&message_ref)?;
+                delete_dependents(&self.structsy, &mut transaction, &message.id)?;
+                deleted += 1;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(deleted)
+    }
+}
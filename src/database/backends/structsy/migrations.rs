@@ -0,0 +1,916 @@
+use structsy::derive::Persistent;
+use structsy::{PrepareOpen, SRes, Structsy, StructsyError, StructsyTx};
+
+use crate::database::backends::structsy::message::MessageToken;
+use crate::services::message::model::Message;
+use crate::services::room::model::{Room, RoomAccess, RoomMode};
+use crate::services::user::model::{User, UserRole, UserStatus};
+use crate::utils::snowflake_generator;
+
+/// Layouts the models had before a migration changed them.
+///
+/// Structsy identifies a struct by its name, so every old layout keeps the name of its model
+/// and lives in its own module instead.
+mod layouts {
+    pub mod message_v0 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct Message {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub author_id: i64,
+            pub room_id: i64,
+            pub content: String,
+        }
+    }
+
+    pub mod message_v1 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct Message {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub author_id: i64,
+            pub room_id: i64,
+            pub content: String,
+            pub created_at: i64,
+        }
+    }
+
+    pub mod message_v2 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct Message {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub author_id: i64,
+            pub room_id: i64,
+            pub content: String,
+            pub created_at: i64,
+            pub edited_at: Option<i64>,
+        }
+    }
+
+    pub mod message_v3 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct Message {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub author_id: i64,
+            pub room_id: i64,
+            pub content: String,
+            pub created_at: i64,
+            pub edited_at: Option<i64>,
+            pub deleted_at: Option<i64>,
+            pub deleted_by: Option<i64>,
+            pub deletion_reason: Option<String>,
+        }
+    }
+
+    pub mod message_v4 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct Message {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub author_id: i64,
+            pub room_id: i64,
+            pub reply_to_id: Option<i64>,
+            pub thread_id: Option<i64>,
+            pub content: String,
+            pub created_at: i64,
+            pub edited_at: Option<i64>,
+            pub deleted_at: Option<i64>,
+            pub deleted_by: Option<i64>,
+            pub deletion_reason: Option<String>,
+        }
+    }
+
+    pub mod room_v0 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct Room {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            #[index(mode = "exclusive")]
+            pub name: String,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod room_v1 {
+        use structsy::derive::Persistent;
+
+        use crate::services::room::model::RoomMode;
+
+        #[derive(Persistent)]
+        pub struct Room {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            #[index(mode = "exclusive")]
+            pub name: String,
+            pub mode: RoomMode,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod room_v2 {
+        use structsy::derive::Persistent;
+
+        use crate::services::room::model::RoomMode;
+
+        #[derive(Persistent)]
+        pub struct Room {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            #[index(mode = "exclusive")]
+            pub name: String,
+            pub mode: RoomMode,
+            pub message_max_age: Option<u64>,
+            pub message_max_count: Option<u64>,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod room_v3 {
+        use structsy::derive::Persistent;
+
+        use crate::services::room::model::{RoomAccess, RoomMode};
+
+        #[derive(Persistent)]
+        pub struct Room {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            #[index(mode = "exclusive")]
+            pub name: String,
+            pub mode: RoomMode,
+            pub access: RoomAccess,
+            pub password_hash: Option<String>,
+            pub message_max_age: Option<u64>,
+            pub message_max_count: Option<u64>,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod room_v4 {
+        use structsy::derive::Persistent;
+
+        use crate::services::room::model::{RoomAccess, RoomMode};
+
+        #[derive(Persistent)]
+        pub struct Room {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            #[index(mode = "exclusive")]
+            pub name: String,
+            pub mode: RoomMode,
+            pub access: RoomAccess,
+            pub password_hash: Option<String>,
+            pub message_max_age: Option<u64>,
+            pub message_max_count: Option<u64>,
+            pub banned_session_ids: Vec<i64>,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod room_v5 {
+        use structsy::derive::Persistent;
+
+        use crate::services::room::model::{RoomAccess, RoomMode};
+
+        #[derive(Persistent)]
+        pub struct Room {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            #[index(mode = "exclusive")]
+            pub name: String,
+            pub mode: RoomMode,
+            pub access: RoomAccess,
+            pub password_hash: Option<String>,
+            pub message_max_age: Option<u64>,
+            pub message_max_count: Option<u64>,
+            pub slow_mode_interval: Option<u64>,
+            pub banned_session_ids: Vec<i64>,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod user_v0 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct User {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub username: String,
+            pub room_id: i64,
+            pub session_id: i64,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod user_v1 {
+        use structsy::derive::Persistent;
+
+        #[derive(Persistent)]
+        pub struct User {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub username: String,
+            pub room_id: i64,
+            pub session_id: i64,
+            pub last_read_message_id: Option<i64>,
+            pub active_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod user_v2 {
+        use structsy::derive::Persistent;
+
+        use crate::services::user::model::UserStatus;
+
+        #[derive(Persistent)]
+        pub struct User {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub username: String,
+            pub room_id: i64,
+            pub session_id: i64,
+            pub last_read_message_id: Option<i64>,
+            pub status: UserStatus,
+            pub status_text: Option<String>,
+            pub active_connection_ids: Vec<i64>,
+            pub idle_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod user_v3 {
+        use structsy::derive::Persistent;
+
+        use crate::services::user::model::{UserRole, UserStatus};
+
+        #[derive(Persistent)]
+        pub struct User {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub username: String,
+            pub room_id: i64,
+            pub session_id: i64,
+            pub role: UserRole,
+            pub last_read_message_id: Option<i64>,
+            pub status: UserStatus,
+            pub status_text: Option<String>,
+            pub active_connection_ids: Vec<i64>,
+            pub idle_connection_ids: Vec<i64>,
+        }
+    }
+
+    pub mod user_v4 {
+        use structsy::derive::Persistent;
+
+        use crate::services::user::model::{UserRole, UserStatus};
+
+        #[derive(Persistent)]
+        pub struct User {
+            #[index(mode = "exclusive")]
+            pub id: i64,
+            pub username: String,
+            pub room_id: i64,
+            pub session_id: i64,
+            pub role: UserRole,
+            pub last_read_message_id: Option<i64>,
+            pub status: UserStatus,
+            pub status_text: Option<String>,
+            pub muted_until: Option<i64>,
+            pub active_connection_ids: Vec<i64>,
+            pub idle_connection_ids: Vec<i64>,
+        }
+    }
+}
+
+enum Step {
+    /// Moves records to a new layout of a model, runs before the database is opened
+    Layout(fn(&PrepareOpen) -> SRes<()>),
+    /// Works with records in their latest layouts, runs after every model is defined
+    Data(fn(&Structsy) -> SRes<()>),
+}
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    step: Step,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Add created_at to Message",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::message_v0::Message, layouts::message_v1::Message>()
+        }),
+    },
+    Migration {
+        version: 2,
+        description: "Add mode to Room",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::room_v0::Room, layouts::room_v1::Room>()
+        }),
+    },
+    Migration {
+        version: 3,
+        description: "Add message retention settings to Room",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::room_v1::Room, layouts::room_v2::Room>()
+        }),
+    },
+    Migration {
+        version: 4,
+        description: "Index message contents for search",
+        step: Step::Data(|database| {
+            let mut transaction = database.begin()?;
+
+            for (_, message) in database.scan::<Message>()? {
+                for token in MessageToken::tokenize(&message) {
+                    transaction.insert(&token)?;
+                }
+            }
+
+            transaction.commit()
+        }),
+    },
+    Migration {
+        version: 5,
+        description: "Add edited_at to Message",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::message_v1::Message, layouts::message_v2::Message>()
+        }),
+    },
+    Migration {
+        version: 6,
+        description: "Add deletion tombstone to Message",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::message_v2::Message, layouts::message_v3::Message>()
+        }),
+    },
+    Migration {
+        version: 7,
+        description: "Add replies and threads to Message",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::message_v3::Message, layouts::message_v4::Message>()
+        }),
+    },
+    Migration {
+        version: 8,
+        description: "Add mentions to Message",
+        step: Step::Layout(|database| database.migrate::<layouts::message_v4::Message, Message>()),
+    },
+    Migration {
+        version: 9,
+        description: "Add last_read_message_id to User",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::user_v0::User, layouts::user_v1::User>()
+        }),
+    },
+    Migration {
+        version: 10,
+        description: "Add status and idle connections to User",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::user_v1::User, layouts::user_v2::User>()
+        }),
+    },
+    Migration {
+        version: 11,
+        description: "Add access to Room",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::room_v2::Room, layouts::room_v3::Room>()
+        }),
+    },
+    Migration {
+        version: 12,
+        description: "Add role to User",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::user_v2::User, layouts::user_v3::User>()
+        }),
+    },
+    Migration {
+        version: 13,
+        description: "Add banned sessions to Room",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::room_v3::Room, layouts::room_v4::Room>()
+        }),
+    },
+    Migration {
+        version: 14,
+        description: "Add muted_until to User",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::user_v3::User, layouts::user_v4::User>()
+        }),
+    },
+    Migration {
+        version: 15,
+        description: "Add slow_mode_interval to Room",
+        step: Step::Layout(|database| {
+            database.migrate::<layouts::room_v4::Room, layouts::room_v5::Room>()
+        }),
+    },
+    Migration {
+        version: 16,
+        description: "Add last_message_at to User",
+        step: Step::Layout(|database| database.migrate::<layouts::user_v4::User, User>()),
+    },
+    Migration {
+        version: 17,
+        description: "Add message_filters to Room",
+        step: Step::Layout(|database| database.migrate::<layouts::room_v5::Room, Room>()),
+    },
+];
+
+#[derive(Persistent)]
+struct SchemaVersion {
+    version: u32,
+}
+
+impl SchemaVersion {
+    fn latest() -> u32 {
+        MIGRATIONS.last().map_or(0, |migration| migration.version)
+    }
+
+    fn get(database: &Structsy) -> SRes<u32> {
+        database.define::<Self>()?;
+
+        Ok(database
+            .scan::<Self>()?
+            .next()
+            .map_or(0, |(_, schema_version)| schema_version.version))
+    }
+
+    fn set(database: &Structsy, version: u32) -> SRes<()> {
+        let mut transaction = database.begin()?;
+
+        for (id, _) in database.scan::<Self>()? {
+            transaction.delete(&id)?;
+        }
+
+        transaction.insert(&Self { version })?;
+        transaction.commit()
+    }
+}
+
+/// Opens the database file and moves its records to the latest layouts.
+///
+/// Layouts can only be migrated before the database is opened,
+/// so the version is read from a short-lived instance first.
+/// Returns the version the database had, which has to be passed to [`finish`].
+/// Fails on a database written by a newer version of the server.
+pub fn open(path: &str) -> SRes<(Structsy, u32)> {
+    let version = SchemaVersion::get(&Structsy::open(path)?)?;
+
+    if version > SchemaVersion::latest() {
+        return Err(StructsyError::MigrationNotSupported(format!(
+            "Database version {version} is newer than the latest known version {}",
+            SchemaVersion::latest()
+        )));
+    }
+
+    let database = Structsy::prepare_open(path)?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+    {
+        let Step::Layout(run) = migration.step else {
+            continue;
+        };
+
+        info!(
+            "Migrate database to version {}: {}",
+            migration.version, migration.description
+        );
+
+        run(&database)?;
+    }
+
+    Ok((database.open()?, version))
+}
+
+/// Runs the data migrations newer than `version` and saves the latest version.
+///
+/// A new database skips migrations by passing [`latest_version`].
+pub fn finish(database: &Structsy, version: u32) -> SRes<()> {
+    database.define::<SchemaVersion>()?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+    {
+        let Step::Data(run) = migration.step else {
+            continue;
+        };
+
+        info!(
+            "Migrate database to version {}: {}",
+            migration.version, migration.description
+        );
+
+        run(database)?;
+    }
+
+    SchemaVersion::set(database, SchemaVersion::latest())
+}
+
+pub fn latest_version() -> u32 {
+    SchemaVersion::latest()
+}
+
+impl From<layouts::message_v0::Message> for layouts::message_v1::Message {
+    fn from(message: layouts::message_v0::Message) -> Self {
+        Self {
+            id: message.id,
+            author_id: message.author_id,
+            room_id: message.room_id,
+            content: message.content,
+            created_at: snowflake_generator::timestamp(message.id),
+        }
+    }
+}
+
+impl From<layouts::message_v1::Message> for layouts::message_v2::Message {
+    fn from(message: layouts::message_v1::Message) -> Self {
+        Self {
+            id: message.id,
+            author_id: message.author_id,
+            room_id: message.room_id,
+            content: message.content,
+            created_at: message.created_at,
+            edited_at: None,
+        }
+    }
+}
+
+impl From<layouts::message_v2::Message> for layouts::message_v3::Message {
+    fn from(message: layouts::message_v2::Message) -> Self {
+        Self {
+            id: message.id,
+            author_id: message.author_id,
+            room_id: message.room_id,
+            content: message.content,
+            created_at: message.created_at,
+            edited_at: message.edited_at,
+            deleted_at: None,
+            deleted_by: None,
+            deletion_reason: None,
+        }
+    }
+}
+
+impl From<layouts::message_v3::Message> for layouts::message_v4::Message {
+    fn from(message: layouts::message_v3::Message) -> Self {
+        Self {
+            id: message.id,
+            author_id: message.author_id,
+            room_id: message.room_id,
+            reply_to_id: None,
+            thread_id: None,
+            content: message.content,
+            created_at: message.created_at,
+            edited_at: message.edited_at,
+            deleted_at: message.deleted_at,
+            deleted_by: message.deleted_by,
+            deletion_reason: message.deletion_reason,
+        }
+    }
+}
+
+impl From<layouts::message_v4::Message> for Message {
+    fn from(message: layouts::message_v4::Message) -> Self {
+        Self {
+            id: message.id,
+            author_id: message.author_id,
+            room_id: message.room_id,
+            reply_to_id: message.reply_to_id,
+            thread_id: message.thread_id,
+            content: message.content,
+            mentions: Vec::new(),
+            created_at: message.created_at,
+            edited_at: message.edited_at,
+            deleted_at: message.deleted_at,
+            deleted_by: message.deleted_by,
+            deletion_reason: message.deletion_reason,
+        }
+    }
+}
+
+impl From<layouts::room_v0::Room> for layouts::room_v1::Room {
+    fn from(room: layouts::room_v0::Room) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            mode: RoomMode::Ephemeral,
+            active_connection_ids: room.active_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::room_v1::Room> for layouts::room_v2::Room {
+    fn from(room: layouts::room_v1::Room) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            mode: room.mode,
+            message_max_age: None,
+            message_max_count: None,
+            active_connection_ids: room.active_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::room_v2::Room> for layouts::room_v3::Room {
+    fn from(room: layouts::room_v2::Room) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            mode: room.mode,
+            access: RoomAccess::Open,
+            password_hash: None,
+            message_max_age: room.message_max_age,
+            message_max_count: room.message_max_count,
+            active_connection_ids: room.active_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::room_v3::Room> for layouts::room_v4::Room {
+    fn from(room: layouts::room_v3::Room) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            mode: room.mode,
+            access: room.access,
+            password_hash: room.password_hash,
+            message_max_age: room.message_max_age,
+            message_max_count: room.message_max_count,
+            banned_session_ids: Vec::new(),
+            active_connection_ids: room.active_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::room_v4::Room> for layouts::room_v5::Room {
+    fn from(room: layouts::room_v4::Room) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            mode: room.mode,
+            access: room.access,
+            password_hash: room.password_hash,
+            message_max_age: room.message_max_age,
+            message_max_count: room.message_max_count,
+            slow_mode_interval: None,
+            banned_session_ids: room.banned_session_ids,
+            active_connection_ids: room.active_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::room_v5::Room> for Room {
+    fn from(room: layouts::room_v5::Room) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            mode: room.mode,
+            access: room.access,
+            password_hash: room.password_hash,
+            message_max_age: room.message_max_age,
+            message_max_count: room.message_max_count,
+            slow_mode_interval: room.slow_mode_interval,
+            message_filters: Vec::new(),
+            banned_session_ids: room.banned_session_ids,
+            active_connection_ids: room.active_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::user_v0::User> for layouts::user_v1::User {
+    fn from(user: layouts::user_v0::User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            room_id: user.room_id,
+            session_id: user.session_id,
+            last_read_message_id: None,
+            active_connection_ids: user.active_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::user_v1::User> for layouts::user_v2::User {
+    fn from(user: layouts::user_v1::User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            room_id: user.room_id,
+            session_id: user.session_id,
+            last_read_message_id: user.last_read_message_id,
+            // The stored status is the one a user picks, not their presence, which
+            // `get_public_status` derives from connections, so users start out as new ones do
+            status: UserStatus::Online,
+            status_text: None,
+            active_connection_ids: user.active_connection_ids,
+            idle_connection_ids: Vec::new(),
+        }
+    }
+}
+
+impl From<layouts::user_v2::User> for layouts::user_v3::User {
+    fn from(user: layouts::user_v2::User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            room_id: user.room_id,
+            session_id: user.session_id,
+            role: UserRole::Member,
+            last_read_message_id: user.last_read_message_id,
+            status: user.status,
+            status_text: user.status_text,
+            active_connection_ids: user.active_connection_ids,
+            idle_connection_ids: user.idle_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::user_v3::User> for layouts::user_v4::User {
+    fn from(user: layouts::user_v3::User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            room_id: user.room_id,
+            session_id: user.session_id,
+            role: user.role,
+            last_read_message_id: user.last_read_message_id,
+            status: user.status,
+            status_text: user.status_text,
+            muted_until: None,
+            active_connection_ids: user.active_connection_ids,
+            idle_connection_ids: user.idle_connection_ids,
+        }
+    }
+}
+
+impl From<layouts::user_v4::User> for User {
+    fn from(user: layouts::user_v4::User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            room_id: user.room_id,
+            session_id: user.session_id,
+            role: user.role,
+            last_read_message_id: user.last_read_message_id,
+            status: user.status,
+            status_text: user.status_text,
+            muted_until: user.muted_until,
+            last_message_at: None,
+            active_connection_ids: user.active_connection_ids,
+            idle_connection_ids: user.idle_connection_ids,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::database::backends::structsy::StructsyDatabase;
+    use crate::database::repository::Database;
+    use crate::services::room::model::RoomMode;
+
+    /// Database file that is removed once the test is done
+    struct TestPath(PathBuf);
+
+    impl TestPath {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("messenger-{name}-{}.db", process::id()));
+            let _ = fs::remove_file(&path);
+
+            Self(path)
+        }
+
+        fn as_str(&self) -> &str {
+            self.0.to_str().expect("Invalid temporary path")
+        }
+    }
+
+    impl Drop for TestPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn open_migrates_a_v0_database() {
+        let path = TestPath::new("migrations-v0");
+        let message_id = 1 << 22;
+
+        {
+            let database = Structsy::open(path.as_str()).unwrap();
+            database.define::<layouts::message_v0::Message>().unwrap();
+            database.define::<layouts::room_v0::Room>().unwrap();
+            database.define::<layouts::user_v0::User>().unwrap();
+
+            let mut transaction = database.begin().unwrap();
+            transaction
+                .insert(&layouts::room_v0::Room {
+                    id: 1,
+                    name: "room".to_string(),
+                    active_connection_ids: vec![10],
+                })
+                .unwrap();
+            transaction
+                .insert(&layouts::user_v0::User {
+                    id: 2,
+                    username: "user".to_string(),
+                    room_id: 1,
+                    session_id: 3,
+                    active_connection_ids: vec![10],
+                })
+                .unwrap();
+            transaction
+                .insert(&layouts::message_v0::Message {
+                    id: message_id,
+                    author_id: 2,
+                    room_id: 1,
+                    content: "Hello World".to_string(),
+                })
+                .unwrap();
+            transaction.commit().unwrap();
+        }
+
+        let database = StructsyDatabase::open(path.as_str()).unwrap();
+
+        let room = database.rooms().find_by_id(&1).unwrap().unwrap();
+        assert_eq!(room.name, "room");
+        assert_eq!(room.mode, RoomMode::Ephemeral);
+        assert_eq!(room.access, RoomAccess::Open);
+        assert_eq!(room.message_max_age, None);
+        assert!(room.message_filters.is_empty());
+        assert_eq!(room.active_connection_ids, vec![10]);
+
+        let user = database.users().find_by_id(&2).unwrap().unwrap();
+        assert_eq!(user.username, "user");
+        assert_eq!(user.session_id, 3);
+        assert_eq!(user.role, UserRole::Member);
+        assert_eq!(user.status, UserStatus::Online);
+        assert_eq!(user.last_read_message_id, None);
+        assert_eq!(user.last_message_at, None);
+        assert_eq!(user.active_connection_ids, vec![10]);
+
+        let message = database
+            .messages()
+            .find_by_id(&message_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.content, "Hello World");
+        assert_eq!(
+            message.created_at,
+            snowflake_generator::timestamp(message_id)
+        );
+        assert_eq!(message.edited_at, None);
+        assert_eq!(message.deleted_at, None);
+        assert_eq!(message.reply_to_id, None);
+        assert!(message.mentions.is_empty());
+
+        // The search index is filled by the data migration
+        assert_eq!(
+            database
+                .messages()
+                .find_ids_by_token_prefix(&1, "worl")
+                .unwrap(),
+            HashSet::from([message_id])
+        );
+        assert_eq!(
+            SchemaVersion::get(&database.structsy).unwrap(),
+            latest_version()
+        );
+    }
+
+    #[test]
+    fn open_rejects_a_newer_database() {
+        let path = TestPath::new("migrations-newer");
+
+        let database = Structsy::open(path.as_str()).unwrap();
+        database.define::<SchemaVersion>().unwrap();
+        SchemaVersion::set(&database, latest_version() + 1).unwrap();
+        drop(database);
+
+        assert!(open(path.as_str()).is_err());
+    }
+}
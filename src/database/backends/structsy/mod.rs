@@ -0,0 +1,72 @@
+use structsy::{SRes, Structsy};
+
+use crate::database::repository::{
+    Database, DirectMessageRepository, MessageRepository, RoomRepository, SessionRepository,
+    UserRepository,
+};
+use crate::services::direct_message::model::{DirectChannel, DirectMessage};
+use crate::services::message::model::{Message, MessageReaction, MessageRevision};
+use crate::services::room::model::{Room, RoomInvite};
+use crate::services::session::model::Session;
+use crate::services::user::model::User;
+
+use self::message::MessageToken;
+
+mod direct_message;
+mod message;
+mod migrations;
+mod room;
+mod session;
+mod user;
+
+const MEMORY_PATH: &str = ":memory:";
+
+pub struct StructsyDatabase {
+    structsy: Structsy,
+}
+
+impl StructsyDatabase {
+    pub fn open(path: &str) -> SRes<Self> {
+        let (structsy, version) = match path {
+            MEMORY_PATH => (Structsy::memory()?, migrations::latest_version()),
+            path => migrations::open(path)?,
+        };
+
+        structsy.define::<DirectChannel>()?;
+        structsy.define::<DirectMessage>()?;
+        structsy.define::<Message>()?;
+        structsy.define::<MessageReaction>()?;
+        structsy.define::<MessageRevision>()?;
+        structsy.define::<MessageToken>()?;
+        structsy.define::<Room>()?;
+        structsy.define::<RoomInvite>()?;
+        structsy.define::<Session>()?;
+        structsy.define::<User>()?;
+
+        migrations::finish(&structsy, version)?;
+
+        Ok(Self { structsy })
+    }
+}
+
+impl Database for StructsyDatabase {
+    fn sessions(&self) -> &dyn SessionRepository {
+        self
+    }
+
+    fn rooms(&self) -> &dyn RoomRepository {
+        self
+    }
+
+    fn users(&self) -> &dyn UserRepository {
+        self
+    }
+
+    fn messages(&self) -> &dyn MessageRepository {
+        self
+    }
+
+    fn direct_messages(&self) -> &dyn DirectMessageRepository {
+        self
+    }
+}
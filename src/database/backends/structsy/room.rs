@@ -0,0 +1,159 @@
+use structsy::derive::queries;
+use structsy::{SRes, Structsy, StructsyTx};
+
+use crate::database::backends::structsy::message::MessageToken;
+use crate::database::backends::structsy::StructsyDatabase;
+use crate::database::repository::RoomRepository;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::model::Message;
+use crate::services::room::model::{Room, RoomInvite};
+use crate::services::user::model::User;
+
+#[queries(Room)]
+trait RoomQueries {
+    fn filter_by_id(self, id: i64) -> Self;
+    fn filter_by_name(self, name: String) -> Self;
+}
+
+#[queries(RoomInvite)]
+trait RoomInviteQueries {
+    fn filter_by_code(self, code: String) -> Self;
+    fn filter_by_room_id(self, room_id: i64) -> Self;
+}
+
+/// Checks whether a room still exists, rooms are never recreated under the same ID
+pub fn exists(structsy: &Structsy, id: i64) -> SRes<bool> {
+    Ok(structsy
+        .query::<Room>()
+        .filter_by_id(id)
+        .into_iter()
+        .next()
+        .is_some())
+}
+
+impl RoomRepository for StructsyDatabase {
+    fn insert(&self, room: &Room) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(room)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_all(&self) -> Result<Vec<Room>, AppError> {
+        Ok(self.structsy.scan::<Room>()?.map(|data| data.1).collect())
+    }
+
+    fn find_by_id(&self, id: &i64) -> Result<Option<Room>, AppError> {
+        Ok(self
+            .structsy
+            .query::<Room>()
+            .filter_by_id(*id)
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn find_by_name(&self, name: &str) -> Result<Option<Room>, AppError> {
+        Ok(self
+            .structsy
+            .query::<Room>()
+            .filter_by_name(name.to_string())
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn update(&self, room: &Room) -> Result<(), AppError> {
+        let Some((room_ref, _)) = self
+            .structsy
+            .query::<Room>()
+            .filter_by_id(room.id)
+            .into_iter()
+            .next()
+        else {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        };
+
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.update(&room_ref, room)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn delete(&self, id: &i64) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        for (room_ref, _) in self.structsy.query::<Room>().filter_by_id(*id) {
+            transaction.delete(&room_ref)?;
+        }
+
+        for (invite_ref, _) in self.structsy.query::<RoomInvite>().filter_by_room_id(*id) {
+            transaction.delete(&invite_ref)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn import(&self, room: &Room, users: &[User], messages: &[Message]) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(room)?;
+
+        for user in users {
+            transaction.insert(user)?;
+        }
+
+        for message in messages {
+            transaction.insert(message)?;
+
+            for token in MessageToken::tokenize(message) {
+                transaction.insert(&token)?;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn insert_invite(&self, invite: &RoomInvite) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(invite)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_invite_by_code(&self, code: &str) -> Result<Option<RoomInvite>, AppError> {
+        Ok(self
+            .structsy
+            .query::<RoomInvite>()
+            .filter_by_code(code.to_string())
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn delete_invite(&self, code: &str) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        for (invite_ref, _) in self
+            .structsy
+            .query::<RoomInvite>()
+            .filter_by_code(code.to_string())
+        {
+            transaction.delete(&invite_ref)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use structsy::derive::queries;
+use structsy::StructsyTx;
+
+use crate::database::backends::structsy::StructsyDatabase;
+use crate::database::repository::SessionRepository;
+use crate::error::AppError;
+use crate::services::session::model::Session;
+
+#[queries(Session)]
+trait SessionQueries {
+    fn filter_by_token(self, token: String) -> Self;
+}
+
+impl SessionRepository for StructsyDatabase {
+    fn insert(&self, session: &Session) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(session)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_by_token(&self, token: &str) -> Result<Option<Session>, AppError> {
+        Ok(self
+            .structsy
+            .query::<Session>()
+            .filter_by_token(token.to_string())
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+}
@@ -0,0 +1,144 @@
+use structsy::derive::queries;
+use structsy::StructsyTx;
+
+use crate::database::backends::structsy::StructsyDatabase;
+use crate::database::repository::UserRepository;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::user::model::User;
+
+#[queries(User)]
+trait UserQueries {
+    fn filter_by_id(self, id: i64) -> Self;
+    fn filter_by_username(self, username: String) -> Self;
+    fn filter_by_room_id(self, room_id: i64) -> Self;
+    fn filter_by_session_id(self, session_id: i64) -> Self;
+}
+
+impl UserRepository for StructsyDatabase {
+    fn insert(&self, user: &User) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.insert(user)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn find_all(&self) -> Result<Vec<User>, AppError> {
+        Ok(self.structsy.scan::<User>()?.map(|data| data.1).collect())
+    }
+
+    fn find_by_id(&self, id: &i64) -> Result<Option<User>, AppError> {
+        Ok(self
+            .structsy
+            .query::<User>()
+            .filter_by_id(*id)
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn find_all_by_room_id(&self, room_id: &i64) -> Result<Vec<User>, AppError> {
+        Ok(self
+            .structsy
+            .query::<User>()
+            .filter_by_room_id(*room_id)
+            .into_iter()
+            .map(|data| data.1)
+            .collect())
+    }
+
+    fn find_by_username_and_room_id(
+        &self,
+        username: &str,
+        room_id: &i64,
+    ) -> Result<Option<User>, AppError> {
+        Ok(self
+            .structsy
+            .query::<User>()
+            .filter_by_username(username.to_string())
+            .filter_by_room_id(*room_id)
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn find_by_session_id_and_room_id(
+        &self,
+        session_id: &i64,
+        room_id: &i64,
+    ) -> Result<Option<User>, AppError> {
+        Ok(self
+            .structsy
+            .query::<User>()
+            .filter_by_session_id(*session_id)
+            .filter_by_room_id(*room_id)
+            .into_iter()
+            .next()
+            .map(|data| data.1))
+    }
+
+    fn update(&self, user: &User) -> Result<(), AppError> {
+        let Some((user_ref, _)) = self
+            .structsy
+            .query::<User>()
+            .filter_by_id(user.id)
+            .into_iter()
+            .next()
+        else {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        };
+
+        let mut transaction = self.structsy.begin()?;
+
+        transaction.update(&user_ref, user)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn update_last_message_at(
+        &self,
+        id: &i64,
+        last_message_at: i64,
+        since: Option<i64>,
+    ) -> Result<bool, AppError> {
+        let Some((user_ref, _)) = self
+            .structsy
+            .query::<User>()
+            .filter_by_id(*id)
+            .into_iter()
+            .next()
+        else {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        };
+
+        // Reading within the transaction makes a concurrent update fail the commit
+        let mut transaction = self.structsy.begin()?;
+        let Some(mut user) = transaction.read(&user_ref)? else {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        };
+
+        if since.is_some_and(|since| user.last_message_at.is_some_and(|at| at > since)) {
+            return Ok(false);
+        }
+
+        user.last_message_at = Some(last_message_at);
+        transaction.update(&user_ref, &user)?;
+        transaction.commit()?;
+
+        Ok(true)
+    }
+
+    fn delete_by_room_id(&self, room_id: &i64) -> Result<(), AppError> {
+        let mut transaction = self.structsy.begin()?;
+
+        for (user_ref, _) in self.structsy.query::<User>().filter_by_room_id(*room_id) {
+            transaction.delete(&user_ref)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
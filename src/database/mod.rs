@@ -0,0 +1,53 @@
+use std::env;
+
+use lazy_static::lazy_static;
+
+use crate::database::backends::sqlite::SqliteDatabase;
+use crate::database::backends::structsy::StructsyDatabase;
+use crate::database::repository::Database;
+use crate::services::room::model::Room;
+use crate::services::user::model::User;
+
+mod backends;
+pub mod repository;
+
+const MEMORY_PATH: &str = ":memory:";
+
+lazy_static! {
+    static ref DATABASE: Box<dyn Database> = {
+        let backend = env::var("MESSENGER_DATABASE_BACKEND").unwrap_or_else(|_| "structsy".into());
+        let path = env::var("MESSENGER_DATABASE_PATH").unwrap_or_else(|_| MEMORY_PATH.into());
+
+        match backend.as_str() {
+            "structsy" => Box::new(
+                StructsyDatabase::open(&path)
+                    .expect("Failed to open and migrate Structsy database"),
+            ),
+            "sqlite" => Box::new(
+                SqliteDatabase::open(&path).expect("Failed to open and migrate SQLite database"),
+            ),
+            backend => panic!("Unknown database backend: {backend}"),
+        }
+    };
+}
+
+pub fn get() -> &'static dyn Database {
+    DATABASE.as_ref()
+}
+
+pub fn init() {
+    info!("Initialize Database");
+
+    lazy_static::initialize(&DATABASE);
+}
+
+/// Whether nothing is kept once the process exits
+pub fn is_in_memory() -> bool {
+    !env::var("MESSENGER_DATABASE_PATH").is_ok_and(|path| path != MEMORY_PATH)
+}
+
+pub fn reset_active_connections() {
+    // Connections don't survive a restart, so the ones left in a file are stale
+    Room::reset_active_connections().expect("Failed to reset Room connections");
+    User::reset_active_connections().expect("Failed to reset User connections");
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::AppError;
+use crate::services::direct_message::model::{DirectChannel, DirectMessage};
+use crate::services::message::model::{Message, MessageReaction, MessageRevision};
+use crate::services::room::model::{Room, RoomInvite};
+use crate::services::session::model::Session;
+use crate::services::user::model::User;
+
+/// Storage engine the models are kept in, implemented by every backend
+pub trait Database: Send + Sync {
+    fn sessions(&self) -> &dyn SessionRepository;
+    fn rooms(&self) -> &dyn RoomRepository;
+    fn users(&self) -> &dyn UserRepository;
+    fn messages(&self) -> &dyn MessageRepository;
+    fn direct_messages(&self) -> &dyn DirectMessageRepository;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+pub trait SessionRepository {
+    fn insert(&self, session: &Session) -> Result<(), AppError>;
+    fn find_by_token(&self, token: &str) -> Result<Option<Session>, AppError>;
+}
+
+pub trait RoomRepository {
+    fn insert(&self, room: &Room) -> Result<(), AppError>;
+    fn find_all(&self) -> Result<Vec<Room>, AppError>;
+    fn find_by_id(&self, id: &i64) -> Result<Option<Room>, AppError>;
+    fn find_by_name(&self, name: &str) -> Result<Option<Room>, AppError>;
+    fn update(&self, room: &Room) -> Result<(), AppError>;
+    /// Deletes a room along with its invites
+    fn delete(&self, id: &i64) -> Result<(), AppError>;
+    /// Inserts a room with its users and messages, nothing is written if any of them fails
+    fn import(&self, room: &Room, users: &[User], messages: &[Message]) -> Result<(), AppError>;
+    fn insert_invite(&self, invite: &RoomInvite) -> Result<(), AppError>;
+    fn find_invite_by_code(&self, code: &str) -> Result<Option<RoomInvite>, AppError>;
+    fn delete_invite(&self, code: &str) -> Result<(), AppError>;
+}
+
+pub trait UserRepository {
+    fn insert(&self, user: &User) -> Result<(), AppError>;
+    fn find_all(&self) -> Result<Vec<User>, AppError>;
+    fn find_by_id(&self, id: &i64) -> Result<Option<User>, AppError>;
+    fn find_all_by_room_id(&self, room_id: &i64) -> Result<Vec<User>, AppError>;
+    fn find_by_username_and_room_id(
+        &self,
+        username: &str,
+        room_id: &i64,
+    ) -> Result<Option<User>, AppError>;
+    fn find_by_session_id_and_room_id(
+        &self,
+        session_id: &i64,
+        room_id: &i64,
+    ) -> Result<Option<User>, AppError>;
+    fn update(&self, user: &User) -> Result<(), AppError>;
+    /// Sets when a user last posted unless they already did after `since`,
+    /// checking and setting at once, returns whether it was set
+    fn update_last_message_at(
+        &self,
+        id: &i64,
+        last_message_at: i64,
+        since: Option<i64>,
+    ) -> Result<bool, AppError>;
+    fn delete_by_room_id(&self, room_id: &i64) -> Result<(), AppError>;
+}
+
+pub trait MessageRepository {
+    /// Inserts a message along with the search tokens of its content
+    fn insert(&self, message: &Message) -> Result<(), AppError>;
+    /// Saves an edited message along with the revision holding its previous content
+    fn update(&self, message: &Message, revision: &MessageRevision) -> Result<(), AppError>;
+    fn find_by_id(&self, id: &i64) -> Result<Option<Message>, AppError>;
+    /// Saves a message turned into a tombstone, dropping its search tokens, revisions and reactions
+    fn delete(&self, message: &Message) -> Result<(), AppError>;
+    /// Finds the revisions of a message, oldest first
+    fn find_revisions_by_message_id(&self, id: &i64) -> Result<Vec<MessageRevision>, AppError>;
+    fn insert_reaction(&self, reaction: &MessageReaction) -> Result<(), AppError>;
+    /// Deletes the reaction of a user to a message, returns whether there was one
+    fn delete_reaction(
+        &self,
+        message_id: &i64,
+        user_id: &i64,
+        emoji: &str,
+    ) -> Result<bool, AppError>;
+    /// Finds the reactions to a message, oldest first
+    fn find_reactions_by_message_id(&self, id: &i64) -> Result<Vec<MessageReaction>, AppError>;
+    /// Finds up to `limit` messages of a room with IDs between the exclusive bounds
+    fn find_by_room_id(
+        &self,
+        room_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<Message>, AppError>;
+    /// Finds up to `limit` replies in the thread of a message with IDs between the exclusive bounds
+    fn find_by_thread_id(
+        &self,
+        thread_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<Message>, AppError>;
+    /// Counts the replies that aren't deleted in the threads of each message,
+    /// threads without any are left out
+    fn count_by_thread_ids(&self, thread_ids: &[i64]) -> Result<HashMap<i64, usize>, AppError>;
+    /// Counts messages of a room after the exclusive bound
+    /// that aren't deleted or from `excluded_author_id`
+    fn count_by_room_id(
+        &self,
+        room_id: &i64,
+        after: Option<i64>,
+        excluded_author_id: &i64,
+    ) -> Result<usize, AppError>;
+    /// IDs of the messages in a room with a search token starting with `prefix`
+    fn find_ids_by_token_prefix(
+        &self,
+        room_id: &i64,
+        prefix: &str,
+    ) -> Result<HashSet<i64>, AppError>;
+    /// Deletes messages of a room created before `expired_at`
+    /// or not among the `max_count` newest ones, returns how many were deleted
+    fn delete_expired_by_room_id(
+        &self,
+        room_id: &i64,
+        expired_at: Option<i64>,
+        max_count: Option<u64>,
+    ) -> Result<usize, AppError>;
+    /// Deletes messages whose room doesn't exist anymore, returns how many were deleted
+    fn delete_orphaned(&self) -> Result<usize, AppError>;
+}
+
+pub trait DirectMessageRepository {
+    fn insert_channel(&self, channel: &DirectChannel) -> Result<(), AppError>;
+    fn find_channel_by_user_ids(
+        &self,
+        first_user_id: &i64,
+        second_user_id: &i64,
+    ) -> Result<Option<DirectChannel>, AppError>;
+    fn insert(&self, message: &DirectMessage) -> Result<(), AppError>;
+    /// Finds up to `limit` messages of a channel with IDs between the exclusive bounds
+    fn find_by_channel_id(
+        &self,
+        channel_id: &i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        order: Order,
+        limit: usize,
+    ) -> Result<Vec<DirectMessage>, AppError>;
+    /// Deletes channels whose room doesn't exist anymore with their messages,
+    /// returns how many messages were deleted
+    fn delete_orphaned(&self) -> Result<usize, AppError>;
+}
@@ -1,11 +1,16 @@
 use std::fmt;
 
 use actix::{ActorContext, MailboxError as ActixMailboxError};
+use actix_web::error::BlockingError as ActixBlockingError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
 use actix_web_actors::ws::{CloseCode, CloseReason, WebsocketContext};
 use persy::PersyError;
 use rmp_serde::decode::Error as RmpSerdeDecodeError;
 use rmp_serde::encode::Error as RmpSerdeEncodeError;
+use rusqlite::{Error as RusqliteError, ErrorCode as RusqliteErrorCode};
 use serde::Deserialize;
+use serde_json::{json, Error as SerdeJsonError};
 use structsy::StructsyError;
 use webrtc::data::Error as WebRtcDataError;
 use webrtc::Error as WebRtcError;
@@ -13,11 +18,15 @@ use webrtc::Error as WebRtcError;
 use crate::web_socket::actor::WebSocket;
 use crate::web_socket::connection::WebSocketConnection;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum AppErrorKind {
+    ActixBlockingError(ActixBlockingError),
     ActixMailboxError(ActixMailboxError),
     RmpSerdeDecodeError(RmpSerdeDecodeError),
     RmpSerdeEncodeError(RmpSerdeEncodeError),
+    RusqliteError(RusqliteError),
+    SerdeJsonError(SerdeJsonError),
     StructsyError(StructsyError),
     WebRtcDataError(WebRtcDataError),
     WebRtcError(WebRtcError),
@@ -37,6 +46,10 @@ pub struct AppError {
     #[serde(rename(deserialize = "code"))]
     pub json_code: u32,
     pub message: String,
+    /// Milliseconds until a rate-limited request may be made again
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+    #[allow(dead_code)]
     #[serde(skip)]
     pub kind: AppErrorKind,
 }
@@ -52,10 +65,18 @@ impl AppError {
             http_code,
             json_code: json_code.unwrap_or(http_code as u32),
             message,
+            retry_after: None,
             kind: error.unwrap_or(AppErrorKind::Other(None)),
         }
     }
 
+    pub fn with_retry_after(self, retry_after: u64) -> Self {
+        Self {
+            retry_after: Some(retry_after),
+            ..self
+        }
+    }
+
     pub fn get_safe_message(&self) -> String {
         match self.http_code < 500 {
             true => self.message.to_owned(),
@@ -67,6 +88,17 @@ impl AppError {
     }
 }
 
+impl From<ActixBlockingError> for AppError {
+    fn from(error: ActixBlockingError) -> Self {
+        AppError::new(
+            500,
+            None,
+            format!("Actix blocking error: {error}"),
+            Some(AppErrorKind::ActixBlockingError(error)),
+        )
+    }
+}
+
 impl From<ActixMailboxError> for AppError {
     fn from(error: ActixMailboxError) -> Self {
         AppError::new(
@@ -100,6 +132,38 @@ impl From<RmpSerdeEncodeError> for AppError {
     }
 }
 
+impl From<RusqliteError> for AppError {
+    fn from(error: RusqliteError) -> Self {
+        match error {
+            RusqliteError::QueryReturnedNoRows => {
+                AppErrorTemplate::NotFound(Some(AppErrorKind::RusqliteError(error))).into()
+            }
+            RusqliteError::SqliteFailure(ref failure, _)
+                if failure.code == RusqliteErrorCode::ConstraintViolation =>
+            {
+                AppErrorTemplate::Conflict(Some(AppErrorKind::RusqliteError(error))).into()
+            }
+            _ => AppError::new(
+                500,
+                None,
+                format!("Rusqlite error: {error}"),
+                Some(AppErrorKind::RusqliteError(error)),
+            ),
+        }
+    }
+}
+
+impl From<SerdeJsonError> for AppError {
+    fn from(error: SerdeJsonError) -> Self {
+        AppError::new(
+            500,
+            None,
+            format!("Serde JSON error: {error}"),
+            Some(AppErrorKind::SerdeJsonError(error)),
+        )
+    }
+}
+
 impl From<StructsyError> for AppError {
     fn from(error: StructsyError) -> Self {
         if let StructsyError::PersyError(persy_error) = &error {
@@ -153,6 +217,21 @@ impl fmt::Display for AppError {
     }
 }
 
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.http_code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "code": self.json_code,
+            "message": self.get_safe_message(),
+        }))
+    }
+}
+
 macro_rules! app_error_template {
     ($(($http_code:expr, $json_code:expr, $name:ident, $message:expr);)+) => {
         pub enum AppErrorTemplate {
@@ -177,6 +256,7 @@ app_error_template! {
     // Default HTTP errors
     (400, None, BadRequest, "Bad request");
     (401, None, Unauthorized, "Unauthorized");
+    (403, None, Forbidden, "Forbidden");
     (404, None, NotFound, "Not found");
     (409, None, Conflict, "Method not allowed");
     (500, None, InternalServerError, "Internal server error");
@@ -188,10 +268,25 @@ app_error_template! {
     (400, Some(3004), UsernameTooLong, "Username is too long");
     (400, Some(3005), MessageContentTooShort, "Message content is too short");
     (400, Some(3006), MessageContentTooLong, "Message content is too long");
+    (400, Some(3007), MessageDeletionReasonTooLong, "Message deletion reason is too long");
+    (400, Some(3008), MessageReactionEmojiTooShort, "Message reaction emoji is too short");
+    (400, Some(3009), MessageReactionEmojiTooLong, "Message reaction emoji is too long");
+    (400, Some(3010), UserStatusTextTooLong, "User status text is too long");
+    (400, Some(3011), RoomPasswordTooShort, "Room password is too short");
+    (400, Some(3012), RoomPasswordTooLong, "Room password is too long");
 
     // Invalid body or something else
     (400, Some(4001), UsernameTaken, "The username is taken");
     (400, Some(4002), WebRtcOfferNotRequested, "WebRTC offer wasn't requested");
+    (409, Some(4003), RoomAlreadyExists, "The room already exists");
+    (400, Some(4004), MessageReplyInAnotherRoom, "The replied message is in another room");
+    (403, Some(4005), RoomPasswordInvalid, "The room password is wrong");
+    (403, Some(4006), RoomInviteInvalid, "The room invite is invalid or expired");
+    (403, Some(4007), UserBanned, "The user is banned from the room");
+    (403, Some(4008), UserMuted, "The user is muted");
+    (429, Some(4009), RateLimited, "Too many requests");
+    (429, Some(4010), RoomSlowMode, "Slow mode is on, wait before posting again");
+    (400, Some(4011), MessageContentBlocked, "The message content is blocked by a filter");
 }
 
 macro_rules! websocket_close_error {
@@ -3,7 +3,7 @@ extern crate log;
 
 use std::env;
 
-use actix::SystemService;
+use actix::{Arbiter, Supervisor, SystemService};
 use actix_files::Files;
 use actix_web::middleware::{NormalizePath, TrailingSlash};
 use actix_web::web::get;
@@ -11,13 +11,17 @@ use actix_web::{App, HttpServer};
 use dotenv::dotenv;
 use serde::{Deserialize, Serialize};
 
+use crate::retention::actor::Retention;
+use crate::services::{message, room};
 use crate::utils::snowflake_generator;
 use crate::web_rtc::actor::WebRtc;
 use crate::web_socket::actor::WebSocket;
 
+mod cli;
 mod constants;
 mod database;
 mod error;
+mod retention;
 mod services;
 mod utils;
 mod web_rtc;
@@ -37,11 +41,24 @@ async fn main() -> std::io::Result<()> {
     database::init();
     snowflake_generator::init();
 
+    let arguments: Vec<String> = env::args().skip(1).collect();
+
+    if !arguments.is_empty() {
+        return cli::run(&arguments);
+    }
+
+    database::reset_active_connections();
+    web_rtc::actor::init_rate_limits();
+    web_socket::actor::init_rate_limits();
+    message::filter::init_server_filters();
+    let retention = Retention::from_env();
+
     let ip = env::var("MESSENGER_IP").unwrap_or_else(|_| "127.0.0.1".into());
     let port = env::var("MESSENGER_PORT").unwrap_or_else(|_| "8080".into());
 
     WebRtc::from_registry();
     WebSocket::from_registry();
+    Supervisor::start_in_arbiter(&Arbiter::new().handle(), move |_| retention);
 
     info!("Starting server on {ip} with port {port}");
 
@@ -49,6 +66,11 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(NormalizePath::new(TrailingSlash::Trim))
             .route("/ws", get().to(web_socket::routes::connect))
+            .route("/rooms/{name}/export", get().to(room::routes::export))
+            .route(
+                "/rooms/{name}/messages/search",
+                get().to(room::routes::search_messages),
+            )
             .service(
                 Files::new("", "./static")
                     .redirect_to_slash_directory()
@@ -0,0 +1,79 @@
+use std::env;
+
+use actix::{Actor, AsyncContext, Context, Supervised};
+
+use crate::constants::{RETENTION_PURGE_INTERVAL, ROOM_MESSAGE_MAX_AGE};
+use crate::error::AppError;
+use crate::services::direct_message::model::DirectChannel;
+use crate::services::message::model::Message;
+use crate::services::room::model::Room;
+
+/// Purges messages on an arbiter of its own, so that long purges don't hold up the other actors
+#[derive(Debug)]
+pub struct Retention {
+    message_max_age: Option<u64>,
+    message_max_count: Option<u64>,
+}
+
+impl Retention {
+    /// Reads the server wide limits rooms fall back to.
+    ///
+    /// Panics on an invalid value, so that a typo doesn't silently disable purging.
+    pub fn from_env() -> Self {
+        let parse = |name, max: u64| {
+            env::var(name).ok().map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|value| (1..=max).contains(value))
+                    .unwrap_or_else(|| panic!("{name}: Invalid value: {value}"))
+            })
+        };
+
+        Self {
+            message_max_age: parse("MESSENGER_MESSAGE_MAX_AGE", ROOM_MESSAGE_MAX_AGE.as_secs()),
+            message_max_count: parse("MESSENGER_MESSAGE_MAX_COUNT", u64::MAX),
+        }
+    }
+
+    fn purge(&self) -> Result<(), AppError> {
+        let mut deleted = Message::delete_orphaned()?;
+
+        deleted += DirectChannel::delete_orphaned()?;
+
+        for room in Room::find_all()? {
+            deleted += Message::delete_expired_by_room_id(
+                &room.id,
+                room.message_max_age.or(self.message_max_age),
+                room.message_max_count.or(self.message_max_count),
+            )?;
+        }
+
+        if deleted != 0 {
+            info!("Purged {deleted} messages");
+        }
+
+        Ok(())
+    }
+}
+
+impl Supervised for Retention {
+    fn restarting(&mut self, _: &mut <Self as Actor>::Context) {
+        warn!("Retention service restarting")
+    }
+}
+
+impl Actor for Retention {
+    type Context = Context<Self>;
+
+    fn started(&mut self, context: &mut Self::Context) {
+        info!("Retention service started");
+
+        context.run_interval(RETENTION_PURGE_INTERVAL, |actor, _| {
+            if let Err(error) = actor.purge() {
+                error!("Failed to purge messages: {error}");
+            }
+        });
+    }
+}
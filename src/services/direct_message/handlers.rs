@@ -0,0 +1,83 @@
+use actix::Context;
+
+use crate::constants::MESSAGE_PAGE_DEFAULT_LIMIT;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::direct_message::model::DirectMessage;
+use crate::services::message::filter;
+use crate::services::message::model::Message;
+use crate::services::room::model::Room;
+use crate::services::user::model::User;
+use crate::utils::snowflake_generator;
+use crate::web_rtc::actor::WebRtc;
+use crate::web_rtc::connection::WebRtcConnection;
+use crate::web_rtc::message::{Opcode, WebRtcMessage, WebRtcMessagePayload};
+
+pub fn post_direct_message(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPostDirectMessage { user_id, content } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    Message::check_content_length(&content)?;
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    user.check_muted()?;
+
+    let content = filter::apply(content, &Room::find_by_id(&user.room_id)?)?;
+
+    DirectMessage::create(&user, &snowflake_generator::parse(&user_id)?, content)?;
+
+    Ok(())
+}
+
+pub fn get_direct_messages(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestGetDirectMessages {
+        user_id,
+        before,
+        after,
+        limit,
+    } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let before = before
+        .as_deref()
+        .map(snowflake_generator::parse)
+        .transpose()?;
+    let after = after
+        .as_deref()
+        .map(snowflake_generator::parse)
+        .transpose()?;
+    let user = User::find_by_id(&connection.registered_user_id)?;
+    let (messages, cursor) = DirectMessage::find_page_by_user_ids(
+        &user,
+        &snowflake_generator::parse(&user_id)?,
+        before,
+        after,
+        limit.unwrap_or(MESSAGE_PAGE_DEFAULT_LIMIT),
+    )?;
+
+    let response = WebRtcMessage {
+        id: message.id,
+        connection_id: message.connection_id,
+        opcode: Opcode::Response,
+        payload: WebRtcMessagePayload::ResponseDirectMessages {
+            messages: messages.into_iter().map(Into::into).collect(),
+            cursor: cursor.map(|cursor| cursor.to_string()),
+        },
+    };
+
+    WebRtc::send_message(message.id, response, connection, context);
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use std::cmp::Ordering;
+
+use actix::SystemService;
+use serde::{Deserialize, Serialize};
+use structsy::derive::Persistent;
+
+use crate::database;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::model::find_page;
+use crate::services::user::model::User;
+use crate::utils::snowflake_generator;
+use crate::web_rtc::actor::WebRtc;
+use crate::web_rtc::message::DirectMessageUpdateMessage;
+
+/// Private conversation between two members of a room
+#[derive(Clone, Debug, Persistent)]
+pub struct DirectChannel {
+    #[index(mode = "exclusive")]
+    pub id: i64,
+    pub room_id: i64,
+    /// Lower ID of the two users, so that every pair has a single channel
+    #[index(mode = "cluster")]
+    pub first_user_id: i64,
+    pub second_user_id: i64,
+}
+
+impl DirectChannel {
+    /// Finds the channel of a user with another member of their room, creating it if needed
+    pub fn find_or_create(user: &User, other_user_id: &i64) -> Result<Self, AppError> {
+        let database = database::get();
+
+        if let Some(channel) = Self::find_by_user_ids(user, other_user_id)? {
+            return Ok(channel);
+        }
+
+        let (first_user_id, second_user_id) = Self::order_user_ids(&user.id, other_user_id);
+        let channel = Self {
+            id: snowflake_generator::generate(),
+            room_id: user.room_id,
+            first_user_id,
+            second_user_id,
+        };
+
+        database.direct_messages().insert_channel(&channel)?;
+
+        Ok(channel)
+    }
+
+    /// Finds the channel of a user with another member of their room, if they have talked yet
+    pub fn find_by_user_ids(user: &User, other_user_id: &i64) -> Result<Option<Self>, AppError> {
+        let database = database::get();
+        let other_user = User::find_by_id(other_user_id)?;
+
+        if other_user.room_id != user.room_id {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        if other_user.id == user.id {
+            return Err(AppErrorTemplate::BadRequest(None).into());
+        }
+
+        let (first_user_id, second_user_id) = Self::order_user_ids(&user.id, &other_user.id);
+
+        database
+            .direct_messages()
+            .find_channel_by_user_ids(&first_user_id, &second_user_id)
+    }
+
+    /// Deletes channels whose room doesn't exist anymore along with their messages,
+    /// returns how many messages were deleted
+    pub fn delete_orphaned() -> Result<usize, AppError> {
+        let database = database::get();
+
+        database.direct_messages().delete_orphaned()
+    }
+
+    fn order_user_ids(user_id: &i64, other_user_id: &i64) -> (i64, i64) {
+        match user_id.cmp(other_user_id) {
+            Ordering::Greater => (*other_user_id, *user_id),
+            _ => (*user_id, *other_user_id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Persistent)]
+pub struct DirectMessage {
+    #[index(mode = "exclusive")]
+    pub id: i64,
+    #[index(mode = "cluster")]
+    pub channel_id: i64,
+    pub author_id: i64,
+    pub recipient_id: i64,
+    pub content: String,
+    pub created_at: i64,
+}
+
+impl DirectMessage {
+    /// Sends a message to another member of the room of `author`, only the two of them get it
+    pub fn create(author: &User, recipient_id: &i64, content: String) -> Result<Self, AppError> {
+        let database = database::get();
+        let channel = DirectChannel::find_or_create(author, recipient_id)?;
+
+        let id = snowflake_generator::generate();
+        let message = Self {
+            id,
+            channel_id: channel.id,
+            author_id: author.id,
+            recipient_id: *recipient_id,
+            content,
+            created_at: snowflake_generator::timestamp(id),
+        };
+
+        database.direct_messages().insert(&message)?;
+
+        WebRtc::from_registry().do_send(DirectMessageUpdateMessage {
+            message: message.clone(),
+        });
+
+        Ok(message)
+    }
+
+    /// Finds up to `limit` messages between a user and another member of their room,
+    /// paged like the messages of a room, see [`find_page`]
+    pub fn find_page_by_user_ids(
+        user: &User,
+        other_user_id: &i64,
+        before: Option<i64>,
+        after: Option<i64>,
+        limit: usize,
+    ) -> Result<(Vec<Self>, Option<i64>), AppError> {
+        let database = database::get();
+
+        let Some(channel) = DirectChannel::find_by_user_ids(user, other_user_id)? else {
+            return Ok((Vec::new(), None));
+        };
+
+        find_page(
+            before,
+            after,
+            limit,
+            |after, before, order, limit| {
+                database.direct_messages().find_by_channel_id(
+                    &channel.id,
+                    after,
+                    before,
+                    order,
+                    limit,
+                )
+            },
+            |message| message.id,
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct DirectMessagePublic {
+    pub id: String,
+    pub channel_id: String,
+    pub author_id: String,
+    pub recipient_id: String,
+    pub content: String,
+    pub created_at: i64,
+}
+
+impl From<DirectMessage> for DirectMessagePublic {
+    fn from(message: DirectMessage) -> Self {
+        Self {
+            id: message.id.to_string(),
+            channel_id: message.channel_id.to_string(),
+            author_id: message.author_id.to_string(),
+            recipient_id: message.recipient_id.to_string(),
+            content: message.content,
+            created_at: message.created_at,
+        }
+    }
+}
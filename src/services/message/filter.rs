@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::env;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use structsy::derive::PersistentEmbedded;
+
+use crate::constants::{
+    MESSAGE_FILTER_MAX_PATTERNS, MESSAGE_FILTER_MAX_RULES, MESSAGE_FILTER_REGEX_SIZE_LIMIT,
+};
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::room::model::Room;
+
+lazy_static! {
+    /// Scheme, and host of links with or without a scheme
+    static ref URL_REGEX: Regex =
+        Regex::new(r"(?i)\b(?:([a-z][a-z0-9+.-]*)://)?((?:[a-z0-9-]+\.)+[a-z]{2,})\b(?:[:/?#]\S*)?")
+            .expect("Invalid URL regex");
+    /// Filters every room goes through before its own
+    static ref SERVER_FILTERS: Vec<Box<dyn MessageFilter>> = from_env("MESSENGER_MESSAGE_FILTERS");
+    /// Compiled filters of rooms by their IDs
+    static ref ROOM_FILTERS: RwLock<HashMap<i64, Arc<RoomFilters>>> = RwLock::new(HashMap::new());
+}
+
+/// What a filter does with the content it was given, rejecting it is done by returning an error
+#[derive(Debug, Eq, PartialEq)]
+pub enum MessageFilterOutcome {
+    Allow,
+    Rewrite(String),
+}
+
+pub trait MessageFilter: Send + Sync {
+    fn filter(&self, content: &str) -> Result<MessageFilterOutcome, AppError>;
+}
+
+/// Filters a message through the server filters, then through those of its room
+pub fn apply(content: String, room: &Room) -> Result<String, AppError> {
+    let room_filters = get_room_filters(room)?;
+
+    SERVER_FILTERS
+        .iter()
+        .chain(room_filters.filters.iter())
+        .try_fold(content, |content, filter| {
+            Ok(match filter.filter(&content)? {
+                MessageFilterOutcome::Allow => content,
+                MessageFilterOutcome::Rewrite(content) => content,
+            })
+        })
+}
+
+/// Checks rules sent by a moderator, which have to compile and stay within the limits
+pub fn check_rules(rules: &[MessageFilterRule]) -> Result<(), AppError> {
+    let is_valid = rules.len() <= MESSAGE_FILTER_MAX_RULES
+        && rules.iter().all(|rule| {
+            (1..=MESSAGE_FILTER_MAX_PATTERNS).contains(&rule.patterns.len())
+                && rule
+                    .patterns
+                    .iter()
+                    .all(|pattern| !pattern.trim().is_empty())
+        });
+
+    if !is_valid {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    }
+
+    build(rules)?;
+
+    Ok(())
+}
+
+/// Compiles the filters of a room for the messages to come
+pub fn update_room_filters(room: &Room) -> Result<(), AppError> {
+    compile_room_filters(room)?;
+
+    Ok(())
+}
+
+/// Drops the compiled filters of a room that was deleted
+pub fn remove_room_filters(room_id: &i64) {
+    ROOM_FILTERS.write().unwrap().remove(room_id);
+}
+
+/// Reads the server filters up front, so that an invalid configuration stops the startup
+pub fn init_server_filters() {
+    lazy_static::initialize(&SERVER_FILTERS);
+}
+
+/// Filters of a room along with the rules they were compiled from
+struct RoomFilters {
+    rules: Vec<MessageFilterRule>,
+    filters: Vec<Box<dyn MessageFilter>>,
+}
+
+/// Compiled filters of a room, which are compiled again only once its rules change
+fn get_room_filters(room: &Room) -> Result<Arc<RoomFilters>, AppError> {
+    let room_filters = ROOM_FILTERS.read().unwrap().get(&room.id).cloned();
+
+    match room_filters {
+        Some(room_filters) if room_filters.rules == room.message_filters => Ok(room_filters),
+        _ => compile_room_filters(room),
+    }
+}
+
+fn compile_room_filters(room: &Room) -> Result<Arc<RoomFilters>, AppError> {
+    let room_filters = Arc::new(RoomFilters {
+        rules: room.message_filters.clone(),
+        filters: build(&room.message_filters)?,
+    });
+
+    ROOM_FILTERS
+        .write()
+        .unwrap()
+        .insert(room.id, room_filters.clone());
+
+    Ok(room_filters)
+}
+
+fn build(rules: &[MessageFilterRule]) -> Result<Vec<Box<dyn MessageFilter>>, AppError> {
+    rules.iter().map(MessageFilterRule::build).collect()
+}
+
+/// Reads a JSON array of rules from an environment variable, panics when it's invalid
+fn from_env(name: &str) -> Vec<Box<dyn MessageFilter>> {
+    let Ok(value) = env::var(name) else {
+        return Vec::new();
+    };
+
+    serde_json::from_str::<Vec<MessageFilterRule>>(&value)
+        .map_err(AppError::from)
+        .and_then(|rules| build(&rules))
+        .unwrap_or_else(|error| panic!("{name}: {error}"))
+}
+
+/// Matches a word as a whole, with `\b` only on its sides that are word characters,
+/// so that words like `c++` or `:)` match too
+fn word_pattern(word: &str) -> String {
+    let word = word.trim();
+    let boundary = |character: Option<char>| match character {
+        Some(character) if character.is_alphanumeric() || character == '_' => r"\b",
+        _ => "",
+    };
+
+    format!(
+        "{}{}{}",
+        boundary(word.chars().next()),
+        regex::escape(word),
+        boundary(word.chars().next_back()),
+    )
+}
+
+/// Configuration of a built-in filter, as set for the server or a room
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, PersistentEmbedded)]
+pub struct MessageFilterRule {
+    pub kind: MessageFilterKind,
+    pub patterns: Vec<String>,
+    pub action: MessageFilterAction,
+}
+
+impl MessageFilterRule {
+    pub fn build(&self) -> Result<Box<dyn MessageFilter>, AppError> {
+        Ok(match self.kind {
+            MessageFilterKind::WordList => Box::new(PatternFilter::new(
+                &format!(
+                    "(?i){}",
+                    self.patterns
+                        .iter()
+                        .map(|word| word_pattern(word))
+                        .collect::<Vec<_>>()
+                        .join("|")
+                ),
+                self.action,
+            )?),
+            MessageFilterKind::Regex => Box::new(PatternFilter::new(
+                &self
+                    .patterns
+                    .iter()
+                    .map(|pattern| format!("(?:{pattern})"))
+                    .collect::<Vec<_>>()
+                    .join("|"),
+                self.action,
+            )?),
+            MessageFilterKind::Url => Box::new(UrlFilter {
+                domains: self
+                    .patterns
+                    .iter()
+                    .map(|domain| domain.trim().trim_matches('.').to_lowercase())
+                    .collect(),
+                action: self.action,
+            }),
+        })
+    }
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize_repr, Serialize_repr, PersistentEmbedded,
+)]
+#[repr(u8)]
+pub enum MessageFilterKind {
+    /// Whole words, whatever their case
+    #[default]
+    WordList = 0,
+    Regex = 1,
+    /// Links to the domains or their subdomains, `*` for any link with a scheme or `www.`
+    Url = 2,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize_repr, Serialize_repr, PersistentEmbedded,
+)]
+#[repr(u8)]
+pub enum MessageFilterAction {
+    #[default]
+    Reject = 0,
+    /// Replaces every character of the matches with `*`
+    Mask = 1,
+}
+
+/// Filters the matches of a regex
+struct PatternFilter {
+    regex: Regex,
+    action: MessageFilterAction,
+}
+
+impl PatternFilter {
+    fn new(pattern: &str, action: MessageFilterAction) -> Result<Self, AppError> {
+        let regex = RegexBuilder::new(pattern)
+            .size_limit(MESSAGE_FILTER_REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|error| {
+                AppError::new(400, None, format!("Invalid message filter: {error}"), None)
+            })?;
+
+        Ok(Self { regex, action })
+    }
+}
+
+impl MessageFilter for PatternFilter {
+    fn filter(&self, content: &str) -> Result<MessageFilterOutcome, AppError> {
+        let matches = self
+            .regex
+            .find_iter(content)
+            .filter(|found| !found.is_empty())
+            .map(|found| found.range());
+
+        apply_action(content, matches, self.action)
+    }
+}
+
+/// Filters links to blocked domains
+struct UrlFilter {
+    domains: Vec<String>,
+    action: MessageFilterAction,
+}
+
+impl UrlFilter {
+    fn is_blocked(&self, host: &str, is_link: bool) -> bool {
+        self.domains.iter().any(|domain| match domain.as_str() {
+            "*" => is_link,
+            domain => {
+                host == domain
+                    || host
+                        .strip_suffix(domain)
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            }
+        })
+    }
+}
+
+impl MessageFilter for UrlFilter {
+    fn filter(&self, content: &str) -> Result<MessageFilterOutcome, AppError> {
+        let matches = URL_REGEX.captures_iter(content).filter_map(|captures| {
+            let host = captures.get(2)?.as_str().to_lowercase();
+            let is_link = captures.get(1).is_some() || host.starts_with("www.");
+
+            match self.is_blocked(&host, is_link) {
+                true => captures.get(0).map(|found| found.range()),
+                false => None,
+            }
+        });
+
+        apply_action(content, matches, self.action)
+    }
+}
+
+fn apply_action(
+    content: &str,
+    mut matches: impl Iterator<Item = Range<usize>>,
+    action: MessageFilterAction,
+) -> Result<MessageFilterOutcome, AppError> {
+    let Some(first) = matches.next() else {
+        return Ok(MessageFilterOutcome::Allow);
+    };
+
+    match action {
+        MessageFilterAction::Reject => Err(AppErrorTemplate::MessageContentBlocked(None).into()),
+        MessageFilterAction::Mask => {
+            let mut masked = String::with_capacity(content.len());
+            let mut end = 0;
+
+            for range in [first].into_iter().chain(matches) {
+                masked.push_str(&content[end..range.start]);
+                masked.extend(content[range.clone()].chars().map(|_| '*'));
+                end = range.end;
+            }
+
+            masked.push_str(&content[end..]);
+
+            Ok(MessageFilterOutcome::Rewrite(masked))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(
+        kind: MessageFilterKind,
+        patterns: &[&str],
+        action: MessageFilterAction,
+    ) -> MessageFilterRule {
+        MessageFilterRule {
+            kind,
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            action,
+        }
+    }
+
+    fn mask(kind: MessageFilterKind, patterns: &[&str], content: &str) -> MessageFilterOutcome {
+        rule(kind, patterns, MessageFilterAction::Mask)
+            .build()
+            .unwrap()
+            .filter(content)
+            .unwrap()
+    }
+
+    fn rewrite(content: &str) -> MessageFilterOutcome {
+        MessageFilterOutcome::Rewrite(content.to_string())
+    }
+
+    #[test]
+    fn word_list_matches_whole_words_in_any_case() {
+        let patterns = ["spam", "Eggs"];
+
+        assert_eq!(
+            mask(MessageFilterKind::WordList, &patterns, "SPAM and eggs"),
+            rewrite("**** and ****")
+        );
+        assert_eq!(
+            mask(MessageFilterKind::WordList, &patterns, "spammer eggshell"),
+            MessageFilterOutcome::Allow
+        );
+    }
+
+    #[test]
+    fn word_list_matches_words_ending_in_symbols() {
+        let patterns = ["c++", "@here", ":)"];
+
+        assert_eq!(
+            mask(
+                MessageFilterKind::WordList,
+                &patterns,
+                "c++ is fun :) @here"
+            ),
+            rewrite("*** is fun ** *****")
+        );
+        assert_eq!(
+            mask(MessageFilterKind::WordList, &patterns, "abc++ @hereby"),
+            MessageFilterOutcome::Allow
+        );
+    }
+
+    #[test]
+    fn regex_matches_any_pattern() {
+        let patterns = [r"\d{4}", "fo+"];
+
+        assert_eq!(
+            mask(MessageFilterKind::Regex, &patterns, "pin 1234 foo"),
+            rewrite("pin **** ***")
+        );
+        assert_eq!(
+            mask(MessageFilterKind::Regex, &patterns, "pin 12 f"),
+            MessageFilterOutcome::Allow
+        );
+    }
+
+    #[test]
+    fn url_matches_domains_and_subdomains() {
+        let patterns = ["example.com"];
+
+        assert_eq!(
+            mask(
+                MessageFilterKind::Url,
+                &patterns,
+                "see https://www.Example.com/a?b"
+            ),
+            rewrite("see ***************************")
+        );
+        assert_eq!(
+            mask(MessageFilterKind::Url, &patterns, "see example.com."),
+            rewrite("see ***********.")
+        );
+        assert_eq!(
+            mask(
+                MessageFilterKind::Url,
+                &patterns,
+                "see notexample.com or example.org"
+            ),
+            MessageFilterOutcome::Allow
+        );
+    }
+
+    #[test]
+    fn url_wildcard_matches_links_only() {
+        let patterns = ["*"];
+
+        assert_eq!(
+            mask(
+                MessageFilterKind::Url,
+                &patterns,
+                "http://a.io and www.b.io"
+            ),
+            rewrite("*********** and ********")
+        );
+        assert_eq!(
+            mask(MessageFilterKind::Url, &patterns, "file.txt"),
+            MessageFilterOutcome::Allow
+        );
+    }
+
+    #[test]
+    fn mask_keeps_the_character_count() {
+        assert_eq!(
+            mask(MessageFilterKind::WordList, &["café"], "un café"),
+            rewrite("un ****")
+        );
+    }
+
+    #[test]
+    fn reject_fails_only_on_a_match() {
+        let filter = rule(
+            MessageFilterKind::WordList,
+            &["spam"],
+            MessageFilterAction::Reject,
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            filter.filter("spam").unwrap_err().json_code,
+            AppError::from(AppErrorTemplate::MessageContentBlocked(None)).json_code
+        );
+        assert_eq!(filter.filter("ham").unwrap(), MessageFilterOutcome::Allow);
+    }
+
+    #[test]
+    fn check_rules_accepts_rules_within_the_limits() {
+        let patterns = vec!["word"; MESSAGE_FILTER_MAX_PATTERNS];
+        let rules = vec![
+            rule(
+                MessageFilterKind::WordList,
+                &patterns,
+                MessageFilterAction::Mask
+            );
+            MESSAGE_FILTER_MAX_RULES
+        ];
+
+        assert!(check_rules(&[]).is_ok());
+        assert!(check_rules(&rules).is_ok());
+    }
+
+    #[test]
+    fn check_rules_rejects_too_many_rules() {
+        let rules = vec![
+            rule(
+                MessageFilterKind::WordList,
+                &["word"],
+                MessageFilterAction::Mask
+            );
+            MESSAGE_FILTER_MAX_RULES + 1
+        ];
+
+        assert!(check_rules(&rules).is_err());
+    }
+
+    #[test]
+    fn check_rules_rejects_too_many_or_blank_patterns() {
+        let patterns = vec!["word"; MESSAGE_FILTER_MAX_PATTERNS + 1];
+
+        for patterns in [&patterns[..], &[], &["word", " "]] {
+            let rules = [rule(
+                MessageFilterKind::WordList,
+                patterns,
+                MessageFilterAction::Mask,
+            )];
+
+            assert!(check_rules(&rules).is_err());
+        }
+    }
+
+    #[test]
+    fn check_rules_rejects_invalid_and_oversized_regexes() {
+        let invalid = [rule(
+            MessageFilterKind::Regex,
+            &["("],
+            MessageFilterAction::Reject,
+        )];
+        let oversized = [rule(
+            MessageFilterKind::Regex,
+            &[r"\w{1000}"],
+            MessageFilterAction::Reject,
+        )];
+
+        assert!(check_rules(&invalid).is_err());
+        assert!(check_rules(&oversized).is_err());
+    }
+}
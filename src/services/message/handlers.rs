@@ -1,25 +1,309 @@
 use actix::Context;
 
+use crate::constants::MESSAGE_PAGE_DEFAULT_LIMIT;
 use crate::error::{AppError, AppErrorTemplate};
-use crate::services::message::model::Message;
+use crate::services::message::filter;
+use crate::services::message::model::{Message, MessagePublic, MessageReaction, MessageRevision};
+use crate::services::message::search::{self, SearchQuery};
+use crate::services::room::model::Room;
+use crate::services::user::model::User;
+use crate::utils::snowflake_generator;
+use crate::web_rtc::actor::WebRtc;
 use crate::web_rtc::connection::WebRtcConnection;
-use crate::web_rtc::message::{WebRtcMessage, WebRtcMessagePayload};
+use crate::web_rtc::message::{Opcode, WebRtcMessage, WebRtcMessagePayload};
 
 pub fn post_message(
     message: WebRtcMessage,
     connection: &mut WebRtcConnection,
     _context: &mut Context<WebRtcConnection>,
 ) -> Result<(), AppError> {
-    let WebRtcMessagePayload::RequestPostMessage { content } = message.payload else {
+    let WebRtcMessagePayload::RequestPostMessage {
+        content,
+        reply_to_id,
+    } = message.payload
+    else {
         return Err(AppErrorTemplate::BadRequest(None).into());
     };
 
     Message::check_content_length(&content)?;
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    user.check_muted()?;
+
+    let room = Room::find_by_id(&user.room_id)?;
+    let content = filter::apply(content, &room)?;
+
+    room.take_slow_mode_turn(&user)?;
+
     Message::create(
-        connection.registered_user_id,
-        connection.registered_room_id,
+        user.id,
+        user.room_id,
+        reply_to_id
+            .as_deref()
+            .map(snowflake_generator::parse)
+            .transpose()?,
         content,
     )?;
 
     Ok(())
 }
+
+pub fn patch_message(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPatchMessage {
+        message_id,
+        content,
+    } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    Message::check_content_length(&content)?;
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    user.check_muted()?;
+
+    let room = Room::find_by_id(&user.room_id)?;
+    let content = filter::apply(content, &room)?;
+
+    // Edits count as messages, or they could be used to get around the slow mode
+    room.take_slow_mode_turn(&user)?;
+
+    Message::update_content(
+        &snowflake_generator::parse(&message_id)?,
+        &user.id,
+        &user.room_id,
+        content,
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_message(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestDeleteMessage { message_id, reason } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    if let Some(reason) = &reason {
+        Message::check_deletion_reason_length(reason)?;
+    }
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    Message::delete(&snowflake_generator::parse(&message_id)?, &user, reason)?;
+
+    Ok(())
+}
+
+pub fn put_message_reaction(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPutMessageReaction { message_id, emoji } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    MessageReaction::check_emoji_length(&emoji)?;
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    user.check_muted()?;
+
+    MessageReaction::create(&snowflake_generator::parse(&message_id)?, &user, emoji)?;
+
+    Ok(())
+}
+
+pub fn delete_message_reaction(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestDeleteMessageReaction { message_id, emoji } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    MessageReaction::delete(&snowflake_generator::parse(&message_id)?, &user, &emoji)?;
+
+    Ok(())
+}
+
+pub fn get_messages(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestGetMessages {
+        before,
+        after,
+        limit,
+    } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let before = before
+        .as_deref()
+        .map(snowflake_generator::parse)
+        .transpose()?;
+    let after = after
+        .as_deref()
+        .map(snowflake_generator::parse)
+        .transpose()?;
+    let (messages, cursor) = Message::find_page_by_room_id(
+        &connection.registered_room_id,
+        before,
+        after,
+        limit.unwrap_or(MESSAGE_PAGE_DEFAULT_LIMIT),
+    )?;
+
+    let response = WebRtcMessage {
+        id: message.id,
+        connection_id: message.connection_id,
+        opcode: Opcode::Response,
+        payload: WebRtcMessagePayload::ResponseMessages {
+            messages: MessagePublic::from_messages(messages, &connection.registered_user_id)?,
+            cursor: cursor.map(|cursor| cursor.to_string()),
+        },
+    };
+
+    WebRtc::send_message(message.id, response, connection, context);
+
+    Ok(())
+}
+
+pub fn get_thread_messages(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestGetThreadMessages {
+        message_id,
+        before,
+        after,
+        limit,
+    } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let before = before
+        .as_deref()
+        .map(snowflake_generator::parse)
+        .transpose()?;
+    let after = after
+        .as_deref()
+        .map(snowflake_generator::parse)
+        .transpose()?;
+    let (messages, cursor) = Message::find_page_by_thread_id(
+        &snowflake_generator::parse(&message_id)?,
+        &connection.registered_room_id,
+        before,
+        after,
+        limit.unwrap_or(MESSAGE_PAGE_DEFAULT_LIMIT),
+    )?;
+
+    let response = WebRtcMessage {
+        id: message.id,
+        connection_id: message.connection_id,
+        opcode: Opcode::Response,
+        payload: WebRtcMessagePayload::ResponseMessages {
+            messages: MessagePublic::from_messages(messages, &connection.registered_user_id)?,
+            cursor: cursor.map(|cursor| cursor.to_string()),
+        },
+    };
+
+    WebRtc::send_message(message.id, response, connection, context);
+
+    Ok(())
+}
+
+pub fn search_messages(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestGetMessageSearch {
+        query,
+        author_id,
+        after,
+        before,
+        cursor,
+        limit,
+    } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let query = SearchQuery {
+        text: query,
+        author_id: author_id
+            .as_deref()
+            .map(snowflake_generator::parse)
+            .transpose()?,
+        after,
+        before,
+        cursor: cursor
+            .as_deref()
+            .map(snowflake_generator::parse)
+            .transpose()?,
+        limit: limit.unwrap_or(MESSAGE_PAGE_DEFAULT_LIMIT),
+    };
+    let (messages, cursor) = search::search(&connection.registered_room_id, &query)?;
+
+    let response = WebRtcMessage {
+        id: message.id,
+        connection_id: message.connection_id,
+        opcode: Opcode::Response,
+        payload: WebRtcMessagePayload::ResponseMessages {
+            messages: MessagePublic::from_messages(messages, &connection.registered_user_id)?,
+            cursor: cursor.map(|cursor| cursor.to_string()),
+        },
+    };
+
+    WebRtc::send_message(message.id, response, connection, context);
+
+    Ok(())
+}
+
+pub fn get_message_revisions(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestGetMessageRevisions { message_id } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let revisions = MessageRevision::find_all_by_message_id(
+        &snowflake_generator::parse(&message_id)?,
+        &connection.registered_room_id,
+    )?;
+
+    let response = WebRtcMessage {
+        id: message.id,
+        connection_id: message.connection_id,
+        opcode: Opcode::Response,
+        payload: WebRtcMessagePayload::ResponseMessageRevisions {
+            revisions: revisions.into_iter().map(Into::into).collect(),
+        },
+    };
+
+    WebRtc::send_message(message.id, response, connection, context);
+
+    Ok(())
+}
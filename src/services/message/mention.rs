@@ -0,0 +1,40 @@
+use crate::constants::{USER_USERNAME_MAX_LENGTH, USER_USERNAME_MIN_LENGTH};
+use crate::services::message::model::MessageMention;
+
+/// Finds `@username` mentions at the start of words, turning usernames into user IDs with `resolve`.
+///
+/// Punctuation right after a username is left out unless it is part of it, e.g. in `@alice,`.
+pub fn parse(content: &str, resolve: impl Fn(&str) -> Option<i64>) -> Vec<MessageMention> {
+    let mut mentions = Vec::new();
+    let mut is_word_start = true;
+
+    for (offset, (index, character)) in content.char_indices().enumerate() {
+        if character == '@' && is_word_start {
+            let word = content[index + 1..]
+                .split(char::is_whitespace)
+                .next()
+                .unwrap_or_default();
+            let trimmed = word.trim_end_matches(|character: char| character.is_ascii_punctuation());
+
+            let mention = [word, trimmed]
+                .into_iter()
+                .filter(|username| {
+                    (USER_USERNAME_MIN_LENGTH..=USER_USERNAME_MAX_LENGTH)
+                        .contains(&username.chars().count())
+                })
+                .find_map(|username| resolve(username).map(|user_id| (username, user_id)));
+
+            if let Some((username, user_id)) = mention {
+                mentions.push(MessageMention {
+                    user_id,
+                    offset: offset as u32,
+                    length: username.chars().count() as u32 + 1,
+                });
+            }
+        }
+
+        is_word_start = character.is_whitespace();
+    }
+
+    mentions
+}
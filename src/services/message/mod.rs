@@ -1,2 +1,5 @@
+pub mod filter;
 pub mod handlers;
+pub mod mention;
 pub mod model;
+pub mod search;
@@ -1,19 +1,24 @@
+use std::iter;
+
 use actix::SystemService;
 use serde::{Deserialize, Serialize};
-use structsy::derive::{queries, Persistent};
-use structsy::StructsyTx;
+use structsy::derive::{Persistent, PersistentEmbedded};
 
-use crate::constants::{MESSAGE_CONTENT_MAX_LENGTH, MESSAGE_CONTENT_MIN_LENGTH};
+use crate::constants::{
+    MESSAGE_CONTENT_MAX_LENGTH, MESSAGE_CONTENT_MIN_LENGTH, MESSAGE_DELETION_REASON_MAX_LENGTH,
+    MESSAGE_PAGE_MAX_LIMIT, MESSAGE_REACTION_EMOJI_MAX_LENGTH, MESSAGE_REACTION_EMOJI_MIN_LENGTH,
+};
 use crate::database;
+use crate::database::repository::Order;
 use crate::error::{AppError, AppErrorTemplate};
-use crate::utils::snowflake_generator;
+use crate::services::message::mention;
+use crate::services::user::model::{User, UserRole};
+use crate::utils::{snowflake_generator, time};
 use crate::web_rtc::actor::WebRtc;
-use crate::web_rtc::message::MessageUpdateMessage;
-
-#[queries(Message)]
-trait MessageQueries {
-    fn filter_by_room_id(self, room_id: i64) -> Self;
-}
+use crate::web_rtc::message::{
+    MessageDeleteMessage, MessageMentionMessage, MessageReactionUpdateMessage,
+    MessageUpdateMessage, TypingMessage,
+};
 
 #[derive(Clone, Debug, Persistent)]
 pub struct Message {
@@ -21,43 +26,335 @@ pub struct Message {
     pub id: i64,
     pub author_id: i64,
     pub room_id: i64,
+    /// Message this one replies to
+    pub reply_to_id: Option<i64>,
+    /// First message of the thread this reply is in, threads don't nest
+    pub thread_id: Option<i64>,
     pub content: String,
+    /// Members of the room mentioned in the content
+    pub mentions: Vec<MessageMention>,
+    pub created_at: i64,
+    /// Milliseconds since the UNIX epoch of the last edit
+    pub edited_at: Option<i64>,
+    /// Milliseconds since the UNIX epoch of the deletion, the content is gone from then on
+    pub deleted_at: Option<i64>,
+    /// The author or a moderator who deleted the message
+    pub deleted_by: Option<i64>,
+    pub deletion_reason: Option<String>,
 }
 
 impl Message {
-    pub fn create(author_id: i64, room_id: i64, content: String) -> Result<Self, AppError> {
+    /// Posts a message, replies join the thread of the message they reply to
+    pub fn create(
+        author_id: i64,
+        room_id: i64,
+        reply_to_id: Option<i64>,
+        content: String,
+    ) -> Result<Self, AppError> {
         let database = database::get();
-        let mut transaction = database.begin()?;
 
+        let thread_id = match reply_to_id {
+            Some(reply_to_id) => {
+                let reply_to = Self::find_by_id(&reply_to_id)?;
+
+                if reply_to.room_id != room_id {
+                    return Err(AppErrorTemplate::MessageReplyInAnotherRoom(None).into());
+                }
+
+                if reply_to.deleted_at.is_some() {
+                    return Err(AppErrorTemplate::NotFound(None).into());
+                }
+
+                Some(reply_to.thread_id.unwrap_or(reply_to.id))
+            }
+            None => None,
+        };
+
+        let id = snowflake_generator::generate();
         let message = Self {
-            id: snowflake_generator::generate(),
+            id,
             author_id,
             room_id,
+            reply_to_id,
+            thread_id,
+            mentions: Self::find_mentions(&content, &room_id),
             content,
+            created_at: snowflake_generator::timestamp(id),
+            edited_at: None,
+            deleted_at: None,
+            deleted_by: None,
+            deletion_reason: None,
         };
 
-        transaction.insert(&message)?;
-        transaction.commit()?;
+        database.messages().insert(&message)?;
 
         WebRtc::from_registry().do_send(MessageUpdateMessage {
             message: message.clone(),
             room_id,
         });
+        message.notify_mentioned(&[]);
+        WebRtc::from_registry().do_send(TypingMessage {
+            user_id: author_id,
+            room_id,
+            is_typing: false,
+        });
+
+        // The reply count of the thread root has changed
+        if let Some(thread_id) = thread_id {
+            WebRtc::from_registry().do_send(MessageUpdateMessage {
+                message: Self::find_by_id(&thread_id)?,
+                room_id,
+            });
+        }
 
         Ok(message)
     }
 
-    pub fn find_all_by_room_id(id: &i64) -> Result<Vec<Self>, AppError> {
+    pub fn find_by_id(id: &i64) -> Result<Self, AppError> {
         let database = database::get();
 
-        let messages = database
-            .query::<Self>()
-            .filter_by_room_id(*id)
-            .into_iter()
-            .map(|data| data.1)
+        if let Some(message) = database.messages().find_by_id(id)? {
+            return Ok(message);
+        }
+
+        Err(AppErrorTemplate::NotFound(None).into())
+    }
+
+    /// Replaces the content of a message posted by `author_id`, keeping the previous one as a revision
+    pub fn update_content(
+        id: &i64,
+        author_id: &i64,
+        room_id: &i64,
+        content: String,
+    ) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut message = Self::find_by_id(id)?;
+
+        if &message.room_id != room_id || message.deleted_at.is_some() {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        if &message.author_id != author_id {
+            return Err(AppErrorTemplate::Forbidden(None).into());
+        }
+
+        let revision = MessageRevision {
+            id: snowflake_generator::generate(),
+            message_id: message.id,
+            content: message.content,
+            created_at: message.edited_at.unwrap_or(message.created_at),
+        };
+
+        let previous_mentions = message.mentions;
+
+        message.mentions = Self::find_mentions(&content, &message.room_id);
+        message.content = content;
+        message.edited_at = Some(time::now());
+        database.messages().update(&message, &revision)?;
+
+        WebRtc::from_registry().do_send(MessageUpdateMessage {
+            message: message.clone(),
+            room_id: message.room_id,
+        });
+        message.notify_mentioned(&previous_mentions);
+
+        Ok(message)
+    }
+
+    /// Turns a message into a tombstone, which only authors and moderators can do
+    pub fn delete(id: &i64, user: &User, reason: Option<String>) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut message = Self::find_by_id(id)?;
+
+        if message.room_id != user.room_id || message.deleted_at.is_some() {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        if message.author_id != user.id && user.role < UserRole::Moderator {
+            return Err(AppErrorTemplate::Forbidden(None).into());
+        }
+
+        message.content = String::new();
+        message.mentions.clear();
+        message.deleted_at = Some(time::now());
+        message.deleted_by = Some(user.id);
+        message.deletion_reason = reason;
+        database.messages().delete(&message)?;
+
+        WebRtc::from_registry().do_send(MessageDeleteMessage {
+            message: message.clone(),
+            room_id: message.room_id,
+        });
+
+        Ok(message)
+    }
+
+    /// Lazily goes through all messages of a room, oldest first, loading them page by page
+    pub fn iter_by_room_id(id: &i64) -> impl Iterator<Item = Result<Self, AppError>> {
+        let database = database::get();
+        let id = *id;
+        let mut after = None;
+        let mut page = Vec::new().into_iter();
+        let mut is_done = false;
+
+        iter::from_fn(move || {
+            if let Some(message) = page.next() {
+                return Some(Ok(message));
+            }
+
+            if is_done {
+                return None;
+            }
+
+            match database.messages().find_by_room_id(
+                &id,
+                after,
+                None,
+                Order::Asc,
+                MESSAGE_PAGE_MAX_LIMIT,
+            ) {
+                Ok(messages) => {
+                    is_done = messages.len() < MESSAGE_PAGE_MAX_LIMIT;
+                    after = messages.last().map(|message| message.id);
+                    page = messages.into_iter();
+
+                    page.next().map(Ok)
+                }
+                Err(error) => {
+                    is_done = true;
+
+                    Some(Err(error))
+                }
+            }
+        })
+    }
+
+    /// Finds up to `limit` messages of a room, oldest first.
+    ///
+    /// Without `after` the page is taken from the newest end, so paging goes back in history.
+    /// The returned cursor is the ID to continue from, when there are more messages left.
+    pub fn find_page_by_room_id(
+        id: &i64,
+        before: Option<i64>,
+        after: Option<i64>,
+        limit: usize,
+    ) -> Result<(Vec<Self>, Option<i64>), AppError> {
+        let database = database::get();
+
+        find_page(
+            before,
+            after,
+            limit,
+            |after, before, order, limit| {
+                database
+                    .messages()
+                    .find_by_room_id(id, after, before, order, limit)
+            },
+            |message| message.id,
+        )
+    }
+
+    /// Finds up to `limit` replies in the thread of a message in a room, paged like
+    /// [`Message::find_page_by_room_id`]
+    pub fn find_page_by_thread_id(
+        id: &i64,
+        room_id: &i64,
+        before: Option<i64>,
+        after: Option<i64>,
+        limit: usize,
+    ) -> Result<(Vec<Self>, Option<i64>), AppError> {
+        let database = database::get();
+        let message = Self::find_by_id(id)?;
+
+        if &message.room_id != room_id {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        find_page(
+            before,
+            after,
+            limit,
+            |after, before, order, limit| {
+                database
+                    .messages()
+                    .find_by_thread_id(id, after, before, order, limit)
+            },
+            |message| message.id,
+        )
+    }
+
+    /// Deletes messages of a room that are older than `max_age` seconds
+    /// or don't fit into the `max_count` newest ones
+    pub fn delete_expired_by_room_id(
+        id: &i64,
+        max_age: Option<u64>,
+        max_count: Option<u64>,
+    ) -> Result<usize, AppError> {
+        let database = database::get();
+
+        // An age too large to subtract from now lets every message stay
+        let expired_at = max_age
+            .and_then(|max_age| i64::try_from(max_age).ok()?.checked_mul(1000))
+            .map(|max_age| time::now().saturating_sub(max_age));
+
+        database
+            .messages()
+            .delete_expired_by_room_id(id, expired_at, max_count)
+    }
+
+    /// Deletes messages whose room doesn't exist anymore
+    pub fn delete_orphaned() -> Result<usize, AppError> {
+        let database = database::get();
+
+        database.messages().delete_orphaned()
+    }
+
+    fn find_mentions(content: &str, room_id: &i64) -> Vec<MessageMention> {
+        mention::parse(content, |username| {
+            User::find_by_username_and_room_id(username, room_id)
+                .ok()
+                .map(|user| user.id)
+        })
+    }
+
+    /// Lets the mentioned users know, except the author and those in `previous_mentions`
+    fn notify_mentioned(&self, previous_mentions: &[MessageMention]) {
+        let mut user_ids: Vec<i64> = self
+            .mentions
+            .iter()
+            .map(|mention| mention.user_id)
+            .filter(|user_id| {
+                user_id != &self.author_id
+                    && !previous_mentions
+                        .iter()
+                        .any(|mention| &mention.user_id == user_id)
+            })
             .collect();
 
-        Ok(messages)
+        user_ids.sort_unstable();
+        user_ids.dedup();
+
+        if !user_ids.is_empty() {
+            WebRtc::from_registry().do_send(MessageMentionMessage {
+                message: self.clone(),
+                user_ids,
+            });
+        }
+    }
+
+    /// Counts the replies that aren't deleted in the thread started by the message,
+    /// replies don't start threads
+    pub fn count_replies(&self) -> Result<usize, AppError> {
+        let database = database::get();
+
+        match self.thread_id {
+            Some(_) => Ok(0),
+            None => Ok(database
+                .messages()
+                .count_by_thread_ids(&[self.id])?
+                .remove(&self.id)
+                .unwrap_or(0)),
+        }
     }
 
     pub fn check_content_length(content: &str) -> Result<(), AppError> {
@@ -73,13 +370,102 @@ impl Message {
             _ => Ok(()),
         }
     }
+
+    pub fn check_deletion_reason_length(reason: &str) -> Result<(), AppError> {
+        match reason.chars().count() > MESSAGE_DELETION_REASON_MAX_LENGTH {
+            true => Err(AppErrorTemplate::MessageDeletionReasonTooLong(None).into()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Pages through records by ID, see [`Message::find_page_by_room_id`] for how the bounds work
+pub fn find_page<T>(
+    before: Option<i64>,
+    after: Option<i64>,
+    limit: usize,
+    find: impl FnOnce(Option<i64>, Option<i64>, Order, usize) -> Result<Vec<T>, AppError>,
+    get_id: impl Fn(&T) -> i64,
+) -> Result<(Vec<T>, Option<i64>), AppError> {
+    let limit = limit.clamp(1, MESSAGE_PAGE_MAX_LIMIT);
+    let is_forward = after.is_some() && before.is_none();
+
+    let mut records = find(
+        after,
+        before,
+        match is_forward {
+            true => Order::Asc,
+            false => Order::Desc,
+        },
+        limit + 1,
+    )?;
+
+    let has_more = records.len() > limit;
+    records.truncate(limit);
+
+    let cursor = match has_more {
+        true => records.last().map(get_id),
+        false => None,
+    };
+
+    if !is_forward {
+        records.reverse();
+    }
+
+    Ok((records, cursor))
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 pub struct MessagePublic {
     pub id: String,
     pub author_id: String,
+    pub reply_to_id: Option<String>,
+    pub thread_id: Option<String>,
     pub content: String,
+    pub mentions: Vec<MessageMentionPublic>,
+    pub created_at: i64,
+    pub edited_at: Option<i64>,
+    pub tombstone: Option<MessageTombstonePublic>,
+    pub reactions: Vec<MessageReactionPublic>,
+    /// Number of replies in the thread started by the message
+    pub reply_count: usize,
+}
+
+impl MessagePublic {
+    /// Converts messages along with their reactions as seen by `user_id` and reply counts
+    pub fn from_messages(messages: Vec<Message>, user_id: &i64) -> Result<Vec<Self>, AppError> {
+        let database = database::get();
+        let thread_ids: Vec<i64> = messages
+            .iter()
+            .filter(|message| message.thread_id.is_none())
+            .map(|message| message.id)
+            .collect();
+        // Counted for the whole page at once, as that reads the newer messages of the room
+        let reply_counts = database.messages().count_by_thread_ids(&thread_ids)?;
+
+        messages
+            .into_iter()
+            .map(|message| {
+                let reactions = MessageReaction::find_all_by_message_id(&message.id)?;
+                let reply_count = reply_counts.get(&message.id).copied().unwrap_or(0);
+
+                Ok(Self::new(message, &reactions, reply_count, user_id))
+            })
+            .collect()
+    }
+
+    pub fn new(
+        message: Message,
+        reactions: &[MessageReaction],
+        reply_count: usize,
+        user_id: &i64,
+    ) -> Self {
+        Self {
+            reactions: MessageReactionPublic::aggregate(reactions, user_id),
+            reply_count,
+            ..message.into()
+        }
+    }
 }
 
 impl From<Message> for MessagePublic {
@@ -87,7 +473,228 @@ impl From<Message> for MessagePublic {
         Self {
             id: message.id.to_string(),
             author_id: message.author_id.to_string(),
+            reply_to_id: message
+                .reply_to_id
+                .map(|reply_to_id| reply_to_id.to_string()),
+            thread_id: message.thread_id.map(|thread_id| thread_id.to_string()),
             content: message.content,
+            mentions: message.mentions.into_iter().map(Into::into).collect(),
+            created_at: message.created_at,
+            edited_at: message.edited_at,
+            tombstone: message.deleted_at.map(|deleted_at| MessageTombstonePublic {
+                deleted_at,
+                deleted_by: message.deleted_by.unwrap_or_default().to_string(),
+                reason: message.deletion_reason,
+            }),
+            reactions: Vec::new(),
+            reply_count: 0,
+        }
+    }
+}
+
+/// Position of an `@username` in the content of a message, counted in characters
+#[derive(Clone, Debug, Deserialize, Serialize, PersistentEmbedded)]
+pub struct MessageMention {
+    pub user_id: i64,
+    pub offset: u32,
+    pub length: u32,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct MessageMentionPublic {
+    pub user_id: String,
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl From<MessageMention> for MessageMentionPublic {
+    fn from(mention: MessageMention) -> Self {
+        Self {
+            user_id: mention.user_id.to_string(),
+            offset: mention.offset,
+            length: mention.length,
+        }
+    }
+}
+
+/// Replaces the content of a deleted message
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct MessageTombstonePublic {
+    pub deleted_at: i64,
+    pub deleted_by: String,
+    pub reason: Option<String>,
+}
+
+/// Content a message had before one of its edits
+#[derive(Clone, Debug, Persistent)]
+pub struct MessageRevision {
+    #[index(mode = "exclusive")]
+    pub id: i64,
+    #[index(mode = "cluster")]
+    pub message_id: i64,
+    pub content: String,
+    /// Milliseconds since the UNIX epoch at which the content was posted
+    pub created_at: i64,
+}
+
+impl MessageRevision {
+    /// Finds the revisions of a message in a room, oldest first
+    pub fn find_all_by_message_id(id: &i64, room_id: &i64) -> Result<Vec<Self>, AppError> {
+        let database = database::get();
+        let message = Message::find_by_id(id)?;
+
+        if &message.room_id != room_id {
+            return Err(AppErrorTemplate::NotFound(None).into());
         }
+
+        database.messages().find_revisions_by_message_id(id)
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct MessageRevisionPublic {
+    pub content: String,
+    pub created_at: i64,
+}
+
+impl From<MessageRevision> for MessageRevisionPublic {
+    fn from(revision: MessageRevision) -> Self {
+        Self {
+            content: revision.content,
+            created_at: revision.created_at,
+        }
+    }
+}
+
+/// Emoji a user reacted to a message with, at most once per emoji
+#[derive(Clone, Debug, Persistent)]
+pub struct MessageReaction {
+    #[index(mode = "exclusive")]
+    pub id: i64,
+    #[index(mode = "cluster")]
+    pub message_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+}
+
+impl MessageReaction {
+    /// Reacts to a message in the room of `user`, reacting twice with the same emoji does nothing
+    pub fn create(message_id: &i64, user: &User, emoji: String) -> Result<(), AppError> {
+        let database = database::get();
+        let message = Message::find_by_id(message_id)?;
+
+        if message.room_id != user.room_id || message.deleted_at.is_some() {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        let mut reactions = database
+            .messages()
+            .find_reactions_by_message_id(message_id)?;
+
+        if reactions
+            .iter()
+            .any(|reaction| reaction.user_id == user.id && reaction.emoji == emoji)
+        {
+            return Ok(());
+        }
+
+        let reaction = Self {
+            id: snowflake_generator::generate(),
+            message_id: message.id,
+            user_id: user.id,
+            emoji,
+        };
+
+        database.messages().insert_reaction(&reaction)?;
+        reactions.push(reaction);
+
+        WebRtc::from_registry().do_send(MessageReactionUpdateMessage {
+            message_id: message.id,
+            reactions,
+            room_id: message.room_id,
+        });
+
+        Ok(())
+    }
+
+    /// Takes back a reaction of `user` to a message in their room
+    pub fn delete(message_id: &i64, user: &User, emoji: &str) -> Result<(), AppError> {
+        let database = database::get();
+        let message = Message::find_by_id(message_id)?;
+
+        if message.room_id != user.room_id
+            || !database
+                .messages()
+                .delete_reaction(message_id, &user.id, emoji)?
+        {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        WebRtc::from_registry().do_send(MessageReactionUpdateMessage {
+            message_id: message.id,
+            reactions: database
+                .messages()
+                .find_reactions_by_message_id(message_id)?,
+            room_id: message.room_id,
+        });
+
+        Ok(())
+    }
+
+    /// Finds the reactions to a message, oldest first
+    pub fn find_all_by_message_id(id: &i64) -> Result<Vec<Self>, AppError> {
+        let database = database::get();
+
+        database.messages().find_reactions_by_message_id(id)
+    }
+
+    pub fn check_emoji_length(emoji: &str) -> Result<(), AppError> {
+        let length = emoji.chars().count();
+
+        match length {
+            length if length < MESSAGE_REACTION_EMOJI_MIN_LENGTH => {
+                Err(AppErrorTemplate::MessageReactionEmojiTooShort(None).into())
+            }
+            length if length > MESSAGE_REACTION_EMOJI_MAX_LENGTH => {
+                Err(AppErrorTemplate::MessageReactionEmojiTooLong(None).into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reactions to a message with the same emoji, `me` tells if the receiving user is among them
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct MessageReactionPublic {
+    pub emoji: String,
+    pub count: usize,
+    pub me: bool,
+}
+
+impl MessageReactionPublic {
+    /// Groups reactions by emoji, in the order each emoji was first used
+    pub fn aggregate(reactions: &[MessageReaction], user_id: &i64) -> Vec<Self> {
+        let mut aggregated: Vec<Self> = Vec::new();
+
+        for reaction in reactions {
+            let index = match aggregated
+                .iter()
+                .position(|aggregated| aggregated.emoji == reaction.emoji)
+            {
+                Some(index) => index,
+                None => {
+                    aggregated.push(Self {
+                        emoji: reaction.emoji.to_owned(),
+                        ..Default::default()
+                    });
+                    aggregated.len() - 1
+                }
+            };
+
+            aggregated[index].count += 1;
+            aggregated[index].me |= &reaction.user_id == user_id;
+        }
+
+        aggregated
     }
 }
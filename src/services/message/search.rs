@@ -0,0 +1,99 @@
+use std::collections::{BTreeSet, HashSet};
+
+use crate::constants::{MESSAGE_PAGE_MAX_LIMIT, MESSAGE_SEARCH_TOKEN_MAX_LENGTH};
+use crate::database;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::model::Message;
+use crate::utils::snowflake_generator;
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub author_id: Option<i64>,
+    /// Milliseconds since the UNIX epoch, inclusive
+    pub after: Option<i64>,
+    /// Milliseconds since the UNIX epoch, exclusive
+    pub before: Option<i64>,
+    /// ID of the last message of the previous page
+    pub cursor: Option<i64>,
+    pub limit: usize,
+}
+
+/// Finds up to `limit` messages of a room containing every word of the query, newest first.
+///
+/// Words match as prefixes, so partially typed ones find results too.
+/// The returned cursor is the ID to continue from, when there are more messages left.
+pub fn search(room_id: &i64, query: &SearchQuery) -> Result<(Vec<Message>, Option<i64>), AppError> {
+    let database = database::get();
+    let words = tokenize(&query.text);
+
+    if words.is_empty() {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    }
+
+    let limit = query.limit.clamp(1, MESSAGE_PAGE_MAX_LIMIT);
+    let min_id = query.after.map(snowflake_generator::from_timestamp);
+    let max_id = [
+        query.before.map(snowflake_generator::from_timestamp),
+        query.cursor,
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+
+    let mut message_ids: Option<HashSet<i64>> = None;
+
+    for word in &words {
+        let ids = database
+            .messages()
+            .find_ids_by_token_prefix(room_id, word)?;
+
+        message_ids = Some(match message_ids {
+            Some(message_ids) => message_ids.intersection(&ids).copied().collect(),
+            None => ids,
+        });
+    }
+
+    let message_ids: BTreeSet<i64> = message_ids
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|id| min_id.is_none_or(|min_id| *id >= min_id))
+        .filter(|id| max_id.is_none_or(|max_id| *id < max_id))
+        .collect();
+
+    let mut messages: Vec<Message> = message_ids
+        .into_iter()
+        .rev()
+        .filter_map(|id| Message::find_by_id(&id).ok())
+        .filter(|message| {
+            query
+                .author_id
+                .is_none_or(|author_id| message.author_id == author_id)
+        })
+        .take(limit + 1)
+        .collect();
+
+    let has_more = messages.len() > limit;
+    messages.truncate(limit);
+
+    let cursor = match has_more {
+        true => messages.last().map(|message| message.id),
+        false => None,
+    };
+
+    Ok((messages, cursor))
+}
+
+/// Splits content into lowercase words, each one only once
+pub fn tokenize(content: &str) -> BTreeSet<String> {
+    content
+        .split(|character: char| !character.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            word.chars()
+                .take(MESSAGE_SEARCH_TOKEN_MAX_LENGTH)
+                .flat_map(char::to_lowercase)
+                .collect()
+        })
+        .collect()
+}
@@ -1,3 +1,4 @@
+pub mod direct_message;
 pub mod message;
 pub mod room;
 pub mod session;
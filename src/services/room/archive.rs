@@ -0,0 +1,376 @@
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::database;
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::filter::{self, MessageFilterRule};
+use crate::services::message::mention;
+use crate::services::message::model::Message;
+use crate::services::room::model::{Room, RoomAccess, RoomMode};
+use crate::services::user::model::{User, UserRole, UserStatus};
+use crate::utils::snowflake_generator;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    /// One JSON record per line, which can be imported back
+    #[default]
+    Ndjson,
+    /// IRC-style log of the messages
+    Text,
+}
+
+impl ArchiveFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Ndjson => "application/x-ndjson",
+            ArchiveFormat::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Ndjson => "ndjson",
+            ArchiveFormat::Text => "log",
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = AppError;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "ndjson" => Ok(ArchiveFormat::Ndjson),
+            "text" => Ok(ArchiveFormat::Text),
+            _ => Err(AppErrorTemplate::BadRequest(None).into()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ArchiveRecord {
+    Room {
+        id: String,
+        name: String,
+        mode: RoomMode,
+        message_max_age: Option<u64>,
+        message_max_count: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        slow_mode_interval: Option<u64>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        message_filters: Vec<MessageFilterRule>,
+    },
+    User {
+        id: String,
+        username: String,
+        #[serde(default)]
+        role: UserRole,
+    },
+    Message {
+        id: String,
+        author_id: String,
+        author_username: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to_id: Option<String>,
+        created_at: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        edited_at: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        deleted_at: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        deleted_by: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        deletion_reason: Option<String>,
+        content: String,
+    },
+}
+
+pub type ArchiveLines = Box<dyn Iterator<Item = Result<String, AppError>> + Send>;
+
+/// Lazily renders a room as lines of the given format, messages go oldest first.
+///
+/// Reading the lines blocks on the database.
+pub fn export(room: Room, format: ArchiveFormat) -> Result<ArchiveLines, AppError> {
+    let users = User::find_all_by_room_id(&room.id)?;
+    let usernames: HashMap<i64, String> = users
+        .iter()
+        .map(|user| (user.id, user.username.to_owned()))
+        .collect();
+    let messages = Message::iter_by_room_id(&room.id).map(move |message| {
+        message.map(|message| {
+            let author_username = usernames
+                .get(&message.author_id)
+                .cloned()
+                .unwrap_or_default();
+
+            (message, author_username)
+        })
+    });
+
+    let lines: ArchiveLines = match format {
+        ArchiveFormat::Ndjson => {
+            let room = ArchiveRecord::Room {
+                id: room.id.to_string(),
+                name: room.name,
+                mode: room.mode,
+                message_max_age: room.message_max_age,
+                message_max_count: room.message_max_count,
+                slow_mode_interval: room.slow_mode_interval,
+                message_filters: room.message_filters,
+            };
+            let users = users.into_iter().map(|user| ArchiveRecord::User {
+                id: user.id.to_string(),
+                username: user.username,
+                role: user.role,
+            });
+            let messages = messages.map(|message| {
+                message.map(|(message, author_username)| ArchiveRecord::Message {
+                    id: message.id.to_string(),
+                    author_id: message.author_id.to_string(),
+                    author_username,
+                    reply_to_id: message
+                        .reply_to_id
+                        .map(|reply_to_id| reply_to_id.to_string()),
+                    created_at: format_time(message.created_at).unwrap_or_default(),
+                    edited_at: message.edited_at.and_then(format_time),
+                    deleted_at: message.deleted_at.and_then(format_time),
+                    deleted_by: message.deleted_by.map(|deleted_by| deleted_by.to_string()),
+                    deletion_reason: message.deletion_reason,
+                    content: message.content,
+                })
+            });
+
+            Box::new(
+                [Ok(room)]
+                    .into_iter()
+                    .chain(users.map(Ok))
+                    .chain(messages)
+                    .map(|record| Ok(serde_json::to_string(&record?)? + "\n")),
+            )
+        }
+        ArchiveFormat::Text => Box::new(messages.map(|message| {
+            let (message, author_username) = message?;
+            let prefix = format!(
+                "[{}] <{author_username}>",
+                created_at(message.id)
+                    .map(|created_at| created_at.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+            );
+
+            if message.deleted_at.is_some() {
+                return Ok(format!("{prefix} (deleted)\n"));
+            }
+
+            Ok(message
+                .content
+                .lines()
+                .map(|line| format!("{prefix} {line}\n"))
+                .collect())
+        })),
+    };
+
+    Ok(lines)
+}
+
+/// Restores a room from an NDJSON archive, keeping the original IDs.
+///
+/// Nothing is written unless the whole archive is valid and doesn't conflict with existing rooms.
+/// Imported users can be claimed by the first session joining with their username.
+pub fn import(archive: impl BufRead) -> Result<Room, AppError> {
+    let mut room = None;
+    let mut users = Vec::new();
+    let mut messages = Vec::new();
+
+    for (index, line) in archive.lines().enumerate() {
+        let invalid = |error: &dyn std::fmt::Display| {
+            AppError::new(
+                400,
+                None,
+                format!("Invalid archive line {}: {error}", index + 1),
+                None,
+            )
+        };
+        let line = line.map_err(|error| invalid(&error))?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(&line).map_err(|error| invalid(&error))? {
+            ArchiveRecord::Room {
+                id,
+                name,
+                mode,
+                message_max_age,
+                message_max_count,
+                slow_mode_interval,
+                message_filters,
+            } => {
+                if room.is_some() {
+                    return Err(invalid(&"the room is already defined"));
+                }
+
+                Room::check_name_length(&name)?;
+                Room::check_retention(message_max_age, message_max_count)?;
+                Room::check_slow_mode_interval(slow_mode_interval)?;
+                filter::check_rules(&message_filters)?;
+                room = Some(Room {
+                    id: snowflake_generator::parse(&id)?,
+                    name,
+                    mode,
+                    access: RoomAccess::Open,
+                    password_hash: None,
+                    message_max_age,
+                    message_max_count,
+                    slow_mode_interval,
+                    message_filters,
+                    banned_session_ids: Vec::new(),
+                    active_connection_ids: Vec::new(),
+                });
+            }
+            ArchiveRecord::User { id, username, role } => {
+                let Some(ref room) = room else {
+                    return Err(invalid(&"a user goes before the room"));
+                };
+
+                User::check_username_length(&username)?;
+                users.push(User {
+                    id: snowflake_generator::parse(&id)?,
+                    username,
+                    room_id: room.id,
+                    session_id: User::UNCLAIMED_SESSION_ID,
+                    role,
+                    last_read_message_id: None,
+                    status: UserStatus::Online,
+                    status_text: None,
+                    muted_until: None,
+                    last_message_at: None,
+                    active_connection_ids: Vec::new(),
+                    idle_connection_ids: Vec::new(),
+                });
+            }
+            ArchiveRecord::Message {
+                id,
+                author_id,
+                reply_to_id,
+                edited_at,
+                deleted_at,
+                deleted_by,
+                deletion_reason,
+                content,
+                ..
+            } => {
+                let Some(ref room) = room else {
+                    return Err(invalid(&"a message goes before the room"));
+                };
+
+                if deleted_at.is_none() {
+                    Message::check_content_length(&content)?;
+                }
+
+                if let Some(ref deletion_reason) = deletion_reason {
+                    Message::check_deletion_reason_length(deletion_reason)?;
+                }
+
+                let id = snowflake_generator::parse(&id)?;
+                let parse_time = |time: Option<String>| {
+                    time.map(|time| DateTime::parse_from_rfc3339(&time))
+                        .transpose()
+                        .map(|time| time.map(|time| time.timestamp_millis()))
+                        .map_err(|error| invalid(&error))
+                };
+
+                messages.push(Message {
+                    id,
+                    author_id: snowflake_generator::parse(&author_id)?,
+                    room_id: room.id,
+                    reply_to_id: reply_to_id
+                        .as_deref()
+                        .map(snowflake_generator::parse)
+                        .transpose()?,
+                    thread_id: None,
+                    mentions: Vec::new(),
+                    content,
+                    created_at: snowflake_generator::timestamp(id),
+                    edited_at: parse_time(edited_at)?,
+                    deleted_at: parse_time(deleted_at)?,
+                    deleted_by: deleted_by
+                        .as_deref()
+                        .map(snowflake_generator::parse)
+                        .transpose()?,
+                    deletion_reason,
+                });
+            }
+        }
+    }
+
+    let Some(room) = room else {
+        return Err(AppError::new(
+            400,
+            None,
+            "The archive has no room".to_string(),
+            None,
+        ));
+    };
+
+    if Room::find_by_name(&room.name).is_ok() || Room::find_by_id(&room.id).is_ok() {
+        return Err(AppErrorTemplate::RoomAlreadyExists(None).into());
+    }
+
+    let mut usernames = HashSet::new();
+    let user_ids: HashSet<i64> = users.iter().map(|user| user.id).collect();
+
+    if users.iter().any(|user| !usernames.insert(&user.username))
+        || messages
+            .iter()
+            .any(|message| !user_ids.contains(&message.author_id))
+    {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    }
+
+    messages.sort_by_key(|message| message.id);
+
+    let mut thread_ids = HashMap::new();
+    let user_ids_by_username: HashMap<&str, i64> = users
+        .iter()
+        .map(|user| (user.username.as_str(), user.id))
+        .collect();
+
+    for message in &mut messages {
+        message.mentions = mention::parse(&message.content, |username| {
+            user_ids_by_username.get(username).copied()
+        });
+
+        // Replies come after the message they reply to, whose thread is known by then
+        if let Some(reply_to_id) = message.reply_to_id {
+            let Some(thread_id) = thread_ids.get(&reply_to_id) else {
+                return Err(AppErrorTemplate::BadRequest(None).into());
+            };
+
+            message.thread_id = Some(*thread_id);
+        }
+
+        thread_ids.insert(message.id, message.thread_id.unwrap_or(message.id));
+    }
+
+    let database = database::get();
+
+    database.rooms().import(&room, &users, &messages)?;
+
+    Ok(room)
+}
+
+fn created_at(id: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(snowflake_generator::timestamp(id))
+}
+
+fn format_time(time: i64) -> Option<String> {
+    DateTime::from_timestamp_millis(time)
+        .map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
@@ -1,13 +1,18 @@
 use actix::{
-    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, WrapFuture,
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner,
+    WrapFuture,
 };
 use actix_web_actors::ws::WebsocketContext;
 
 use crate::error::{AppError, AppErrorTemplate};
-use crate::services::room::model::Room;
-use crate::services::user::model::User;
+use crate::services::message::filter;
+use crate::services::room::model::{Room, RoomInvite};
+use crate::services::user::model::{User, UserRole};
+use crate::utils::snowflake_generator;
 use crate::web_rtc;
+use crate::web_rtc::actor::WebRtc;
 use crate::web_rtc::connection::WebRtcConnection;
+use crate::web_rtc::message::{Opcode, WebRtcMessage, WebRtcMessagePayload};
 use crate::web_socket::connection::WebSocketConnection;
 use crate::web_socket::message::{WebSocketMessage, WebSocketMessagePayload};
 
@@ -19,6 +24,10 @@ pub fn get_sdp_offer(
     let WebSocketMessagePayload::RequestGetRoomSdpOffer {
         room_name,
         username,
+        room_mode,
+        room_access,
+        password,
+        invite_code,
     } = message.payload
     else {
         return Err(AppErrorTemplate::BadRequest(None).into());
@@ -27,6 +36,25 @@ pub fn get_sdp_offer(
     Room::check_name_length(&room_name)?;
     User::check_username_length(&username)?;
 
+    let session_id = connection
+        .session_id
+        .ok_or_else(|| AppErrorTemplate::Unauthorized(None))?;
+    let mut invite = None;
+
+    // The room is checked before leaving the previous one, so that a refused join leaves nothing
+    match Room::find_by_name(&room_name) {
+        Ok(room) => {
+            room.check_banned(&session_id)?;
+
+            // Members who have joined before don't need credentials again
+            if User::find_by_session_id_and_room_id(&session_id, &room.id).is_err() {
+                invite = room.check_access(password.as_deref(), invite_code.as_deref())?;
+            }
+        }
+        Err(error) if error.http_code != 404 => return Err(error),
+        Err(_) => {}
+    }
+
     if let (Some(room_id), Some(user_id)) = (
         &connection.registered_room_id,
         &connection.registered_user_id,
@@ -34,21 +62,26 @@ pub fn get_sdp_offer(
         Room::unregister_connection(&connection.id, room_id, user_id)?;
     }
 
-    let session_id = connection
-        .session_id
-        .ok_or_else(|| AppErrorTemplate::Unauthorized(None))?;
-    let room = match Room::find_by_name(&room_name) {
-        Ok(room) => room,
+    // Leaving an ephemeral room as its last member deletes it, so the room is looked up again
+    let (room, role) = match Room::find_by_name(&room_name) {
+        Ok(room) => (room, UserRole::Member),
         Err(error) => {
             if error.http_code != 404 {
                 return Err(error);
             }
 
-            Room::create(room_name)?
+            let room = Room::create(
+                room_name,
+                room_mode.unwrap_or_default(),
+                room_access.unwrap_or_default(),
+                password,
+            )?;
+
+            (room, UserRole::Owner)
         }
     };
 
-    let user = match User::create(username.to_owned(), room.id, session_id) {
+    let user = match User::create(username.to_owned(), room.id, session_id, role) {
         Ok(user) => user,
         Err(error) => {
             if error.http_code != 409 {
@@ -56,14 +89,20 @@ pub fn get_sdp_offer(
             }
 
             let user = User::find_by_username_and_room_id(&username, &room.id)?;
-            if user.session_id != session_id {
+            if user.session_id == User::UNCLAIMED_SESSION_ID {
+                User::claim(&user.id, session_id)?
+            } else if user.session_id != session_id {
                 return Err(AppErrorTemplate::UsernameTaken(None).into());
+            } else {
+                user
             }
-
-            user
         }
     };
 
+    if let Some(invite) = invite {
+        invite.delete()?;
+    }
+
     connection.registered_room_id = Some(room.id);
     connection.registered_user_id = Some(user.id);
     Room::register_connection(connection.id, &room.id, &user.id)?;
@@ -132,6 +171,123 @@ pub fn post_sdp_answer(
     Ok(())
 }
 
+pub fn patch_retention(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPatchRoomRetention {
+        message_max_age,
+        message_max_count,
+    } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    Room::update_retention(&user, message_max_age, message_max_count)?;
+
+    Ok(())
+}
+
+pub fn patch_slow_mode(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPatchRoomSlowMode { slow_mode_interval } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    Room::update_slow_mode(&user, slow_mode_interval)?;
+
+    Ok(())
+}
+
+pub fn patch_message_filters(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPatchRoomMessageFilters { message_filters } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    filter::check_rules(&message_filters)?;
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    Room::update_message_filters(&user, message_filters)?;
+
+    Ok(())
+}
+
+pub fn post_kick(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPostRoomKick { user_id } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    Room::kick(&snowflake_generator::parse(&user_id)?, &user)?;
+
+    Ok(())
+}
+
+pub fn put_ban(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPutRoomBan { user_id } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    Room::ban(&snowflake_generator::parse(&user_id)?, &user)?;
+
+    Ok(())
+}
+
+pub fn post_invite(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPostRoomInvite = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let invite = RoomInvite::create(
+        &connection.registered_room_id,
+        &connection.registered_user_id,
+    )?;
+
+    let response = WebRtcMessage {
+        id: message.id,
+        connection_id: message.connection_id,
+        opcode: Opcode::Response,
+        payload: WebRtcMessagePayload::ResponseRoomInvite {
+            code: invite.code,
+            expires_at: invite.expires_at,
+        },
+    };
+
+    WebRtc::send_message(message.id, response, connection, context);
+
+    Ok(())
+}
+
 // pub fn post_ice_candidate(
 //     message: WebSocketMessage,
 //     connection: &mut WebSocketConnection,
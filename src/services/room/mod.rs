@@ -1,2 +1,4 @@
+pub mod archive;
 pub mod handlers;
 pub mod model;
+pub mod routes;
@@ -1,114 +1,335 @@
-use structsy::derive::{queries, Persistent};
-use structsy::StructsyTx;
+use actix::SystemService;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use structsy::derive::{Persistent, PersistentEmbedded};
 
-use crate::constants::{ROOM_NAME_MAX_LENGTH, ROOM_NAME_MIN_LENGTH};
+use crate::constants::{
+    ROOM_INVITE_MAX_AGE, ROOM_MESSAGE_MAX_AGE, ROOM_NAME_MAX_LENGTH, ROOM_NAME_MIN_LENGTH,
+    ROOM_PASSWORD_MAX_LENGTH, ROOM_PASSWORD_MIN_LENGTH, ROOM_SLOW_MODE_MAX_INTERVAL,
+};
 use crate::database;
 use crate::error::{AppError, AppErrorTemplate};
-use crate::services::user::model::User;
-use crate::utils::snowflake_generator;
+use crate::services::message::filter::{self, MessageFilterRule};
+use crate::services::user::model::{User, UserRole};
+use crate::utils::{password, snowflake_generator, time};
+use crate::web_rtc::actor::WebRtc;
+use crate::web_rtc::message::{RoomUpdateMessage, UserKickMessage};
 
-#[queries(Room)]
-trait RoomQueries {
-    fn filter_by_id(self, id: i64) -> Self;
-    fn filter_by_name(self, name: String) -> Self;
-}
-
-#[derive(Debug, Persistent)]
+#[derive(Clone, Debug, Persistent)]
 pub struct Room {
     #[index(mode = "exclusive")]
     pub id: i64,
     #[index(mode = "exclusive")]
     pub name: String,
+    pub mode: RoomMode,
+    pub access: RoomAccess,
+    /// Argon2 hash of the password of password-protected rooms
+    pub password_hash: Option<String>,
+    /// Seconds after which messages are purged, overrides the server setting
+    pub message_max_age: Option<u64>,
+    /// Number of the newest messages that are kept, overrides the server setting
+    pub message_max_count: Option<u64>,
+    /// Seconds members have to wait between their messages, moderators aren't slowed down
+    pub slow_mode_interval: Option<u64>,
+    /// Filters messages go through after the server ones
+    pub message_filters: Vec<MessageFilterRule>,
+    /// Sessions that may not join the room anymore, whatever username they pick
+    pub banned_session_ids: Vec<i64>,
     pub active_connection_ids: Vec<i64>,
 }
 
 impl Room {
-    pub fn create(name: String) -> Result<Self, AppError> {
+    /// Creates a room, password-protected ones need a `password`
+    pub fn create(
+        name: String,
+        mode: RoomMode,
+        access: RoomAccess,
+        password: Option<String>,
+    ) -> Result<Self, AppError> {
         let database = database::get();
-        let mut transaction = database.begin()?;
+
+        let password_hash = match access {
+            RoomAccess::Password => {
+                let password = password.ok_or(AppErrorTemplate::BadRequest(None))?;
+
+                Self::check_password_length(&password)?;
+                Some(password::hash(&password)?)
+            }
+            _ => None,
+        };
 
         let room = Self {
             id: snowflake_generator::generate(),
             name,
+            mode,
+            access,
+            password_hash,
+            message_max_age: None,
+            message_max_count: None,
+            slow_mode_interval: None,
+            message_filters: Vec::new(),
+            banned_session_ids: Vec::new(),
             active_connection_ids: Vec::new(),
         };
 
-        transaction.insert(&room)?;
-        transaction.commit()?;
+        database.rooms().insert(&room)?;
 
         Ok(room)
     }
 
+    pub fn find_all() -> Result<Vec<Self>, AppError> {
+        let database = database::get();
+
+        database.rooms().find_all()
+    }
+
+    pub fn find_by_id(id: &i64) -> Result<Self, AppError> {
+        let database = database::get();
+
+        if let Some(room) = database.rooms().find_by_id(id)? {
+            return Ok(room);
+        }
+
+        Err(AppErrorTemplate::NotFound(None).into())
+    }
+
     pub fn find_by_name(name: &str) -> Result<Self, AppError> {
         let database = database::get();
 
-        if let Some((_, room)) = database
-            .query::<Self>()
-            .filter_by_name(name.to_string())
-            .into_iter()
-            .next()
-        {
+        if let Some(room) = database.rooms().find_by_name(name)? {
             return Ok(room);
         }
 
         Err(AppErrorTemplate::NotFound(None).into())
     }
 
-    pub fn register_connection(id: i64, room_id: &i64, user_id: &i64) -> Result<(), AppError> {
+    /// Checks the credentials of someone joining the room for the first time.
+    ///
+    /// Returns the invite that let them in, which is spent once they have joined.
+    pub fn check_access(
+        &self,
+        password: Option<&str>,
+        invite_code: Option<&str>,
+    ) -> Result<Option<RoomInvite>, AppError> {
+        match self.access {
+            RoomAccess::Open => Ok(None),
+            RoomAccess::Password => {
+                let is_valid = password
+                    .zip(self.password_hash.as_deref())
+                    .is_some_and(|(password, hash)| password::verify(password, hash));
+
+                match is_valid {
+                    true => Ok(None),
+                    false => Err(AppErrorTemplate::RoomPasswordInvalid(None).into()),
+                }
+            }
+            RoomAccess::InviteOnly => {
+                let Some(invite_code) = invite_code else {
+                    return Err(AppErrorTemplate::RoomInviteInvalid(None).into());
+                };
+
+                RoomInvite::find_valid(invite_code, &self.id).map(Some)
+            }
+        }
+    }
+
+    /// Changes how long the messages of the room of `user` are kept, which moderators can do
+    pub fn update_retention(
+        user: &User,
+        message_max_age: Option<u64>,
+        message_max_count: Option<u64>,
+    ) -> Result<Self, AppError> {
         let database = database::get();
+        let mut room = Self::find_by_id(&user.room_id)?;
 
-        if let Some((room_id, room)) = database
-            .query::<Self>()
-            .filter_by_id(*room_id)
-            .into_iter()
-            .next()
-        {
-            let mut transaction = database.begin()?;
-            let mut room = room;
+        if user.role < UserRole::Moderator {
+            return Err(AppErrorTemplate::Forbidden(None).into());
+        }
+
+        Self::check_retention(message_max_age, message_max_count)?;
+        room.message_max_age = message_max_age;
+        room.message_max_count = message_max_count;
+        database.rooms().update(&room)?;
+
+        WebRtc::from_registry().do_send(RoomUpdateMessage { room: room.clone() });
+
+        Ok(room)
+    }
+
+    /// Changes how long members of the room of `user` wait between messages, which moderators can do
+    pub fn update_slow_mode(
+        user: &User,
+        slow_mode_interval: Option<u64>,
+    ) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut room = Self::find_by_id(&user.room_id)?;
+
+        if user.role < UserRole::Moderator {
+            return Err(AppErrorTemplate::Forbidden(None).into());
+        }
+
+        Self::check_slow_mode_interval(slow_mode_interval)?;
+        room.slow_mode_interval = slow_mode_interval;
+        database.rooms().update(&room)?;
+
+        WebRtc::from_registry().do_send(RoomUpdateMessage { room: room.clone() });
+
+        Ok(room)
+    }
+
+    /// Replaces the message filters of the room of `user`, which moderators can do
+    pub fn update_message_filters(
+        user: &User,
+        message_filters: Vec<MessageFilterRule>,
+    ) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut room = Self::find_by_id(&user.room_id)?;
+
+        if user.role < UserRole::Moderator {
+            return Err(AppErrorTemplate::Forbidden(None).into());
+        }
 
-            User::register_connection(id, user_id)?;
+        room.message_filters = message_filters;
+        filter::update_room_filters(&room)?;
+        database.rooms().update(&room)?;
 
-            room.active_connection_ids.push(id);
-            transaction.update(&room_id, &room)?;
-            transaction.commit()?;
+        WebRtc::from_registry().do_send(RoomUpdateMessage { room: room.clone() });
 
+        Ok(room)
+    }
+
+    /// Records that a user posts now if they have waited long enough since their last message,
+    /// otherwise the error says how many milliseconds are left
+    pub fn take_slow_mode_turn(&self, user: &User) -> Result<(), AppError> {
+        let now = time::now();
+        let slow_mode_interval = self
+            .slow_mode_interval
+            .filter(|_| user.role < UserRole::Moderator)
+            .map(|slow_mode_interval| slow_mode_interval as i64 * 1000);
+
+        if User::update_last_message_at(
+            &user.id,
+            now,
+            slow_mode_interval.map(|slow_mode_interval| now - slow_mode_interval),
+        )? {
             return Ok(());
         }
 
-        Err(AppErrorTemplate::NotFound(None).into())
+        let last_message_at = User::find_by_id(&user.id)?.last_message_at.unwrap_or(now);
+        let retry_after = last_message_at + slow_mode_interval.unwrap_or_default() - now;
+
+        Err(AppError::from(AppErrorTemplate::RoomSlowMode(None))
+            .with_retry_after(retry_after.max(1) as u64))
     }
 
-    pub fn unregister_connection(id: &i64, room_id: &i64, user_id: &i64) -> Result<(), AppError> {
+    /// Disconnects a user from the room of `by`, which moderators can do to members below them
+    pub fn kick(user_id: &i64, by: &User) -> Result<User, AppError> {
+        let user = User::find_by_id(user_id)?;
+
+        user.check_moderated_by(by)?;
+
+        for connection_id in &user.active_connection_ids {
+            Self::unregister_connection(connection_id, &user.room_id, &user.id)?;
+        }
+
+        WebRtc::from_registry().do_send(UserKickMessage {
+            connection_ids: user.active_connection_ids.clone(),
+        });
+
+        Ok(user)
+    }
+
+    /// Kicks a user and keeps their session out of the room for good
+    pub fn ban(user_id: &i64, by: &User) -> Result<Self, AppError> {
         let database = database::get();
+        let user = Self::kick(user_id, by)?;
+        let mut room = Self::find_by_id(&user.room_id)?;
 
-        if let Some((room_id, room)) = database
-            .query::<Self>()
-            .filter_by_id(*room_id)
-            .into_iter()
-            .next()
+        if user.session_id == User::UNCLAIMED_SESSION_ID
+            || room.banned_session_ids.contains(&user.session_id)
         {
-            let mut transaction = database.begin()?;
-            let mut room = room;
+            return Ok(room);
+        }
 
-            User::unregister_connection(id, user_id)?;
+        room.banned_session_ids.push(user.session_id);
+        database.rooms().update(&room)?;
 
-            room.active_connection_ids
-                .retain(|&active_id| &active_id != id);
+        Ok(room)
+    }
 
-            match room.active_connection_ids.is_empty() {
-                true => {
-                    User::delete_by_room_id(&room.id)?;
-                    transaction.delete(&room_id)?
-                }
-                false => transaction.update(&room_id, &room)?,
+    pub fn check_banned(&self, session_id: &i64) -> Result<(), AppError> {
+        match self.banned_session_ids.contains(session_id) {
+            true => Err(AppErrorTemplate::UserBanned(None).into()),
+            false => Ok(()),
+        }
+    }
+
+    pub fn register_connection(id: i64, room_id: &i64, user_id: &i64) -> Result<(), AppError> {
+        let database = database::get();
+        let mut room = Self::find_by_id(room_id)?;
+
+        User::register_connection(id, user_id)?;
+
+        room.active_connection_ids.push(id);
+        database.rooms().update(&room)
+    }
+
+    /// Takes a connection out of a room, which is deleted if it's ephemeral and left empty.
+    ///
+    /// A room or user that is gone counts as already unregistered,
+    /// as kicked connections are unregistered while their socket still holds on to them.
+    pub fn unregister_connection(id: &i64, room_id: &i64, user_id: &i64) -> Result<(), AppError> {
+        let database = database::get();
+        let mut room = match Self::find_by_id(room_id) {
+            Ok(room) => room,
+            Err(error) if error.http_code == 404 => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        match User::unregister_connection(id, user_id) {
+            Err(error) if error.http_code != 404 => return Err(error),
+            _ => {}
+        }
+
+        room.active_connection_ids
+            .retain(|&active_id| &active_id != id);
+
+        match room.active_connection_ids.is_empty() && room.mode == RoomMode::Ephemeral {
+            true => {
+                User::delete_by_room_id(&room.id)?;
+                filter::remove_room_filters(&room.id);
+                database.rooms().delete(&room.id)
             }
+            false => database.rooms().update(&room),
+        }
+    }
 
-            transaction.commit()?;
+    /// Clears connections left from the previous run.
+    ///
+    /// Ephemeral rooms that had members are deleted with their users,
+    /// as if their last member had just left.
+    pub fn reset_active_connections() -> Result<(), AppError> {
+        let database = database::get();
 
-            return Ok(());
+        for mut room in database.rooms().find_all()? {
+            if room.active_connection_ids.is_empty() {
+                continue;
+            }
+
+            if room.mode == RoomMode::Ephemeral {
+                User::delete_by_room_id(&room.id)?;
+                filter::remove_room_filters(&room.id);
+                database.rooms().delete(&room.id)?;
+
+                continue;
+            }
+
+            room.active_connection_ids.clear();
+            database.rooms().update(&room)?;
         }
 
-        Err(AppErrorTemplate::NotFound(None).into())
+        Ok(())
     }
 
     pub fn check_name_length(name: &str) -> Result<(), AppError> {
@@ -124,4 +345,150 @@ impl Room {
             _ => Ok(()),
         }
     }
+
+    pub fn check_password_length(password: &str) -> Result<(), AppError> {
+        let length = password.chars().count();
+
+        match length {
+            length if length < ROOM_PASSWORD_MIN_LENGTH => {
+                Err(AppErrorTemplate::RoomPasswordTooShort(None).into())
+            }
+            length if length > ROOM_PASSWORD_MAX_LENGTH => {
+                Err(AppErrorTemplate::RoomPasswordTooLong(None).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects limits that would purge every message or overflow when turned into milliseconds
+    pub fn check_retention(
+        message_max_age: Option<u64>,
+        message_max_count: Option<u64>,
+    ) -> Result<(), AppError> {
+        if message_max_age.is_some_and(|message_max_age| {
+            message_max_age == 0 || message_max_age > ROOM_MESSAGE_MAX_AGE.as_secs()
+        }) || message_max_count == Some(0)
+        {
+            return Err(AppErrorTemplate::BadRequest(None).into());
+        }
+
+        Ok(())
+    }
+
+    pub fn check_slow_mode_interval(slow_mode_interval: Option<u64>) -> Result<(), AppError> {
+        if slow_mode_interval.is_some_and(|slow_mode_interval| {
+            slow_mode_interval == 0 || slow_mode_interval > ROOM_SLOW_MODE_MAX_INTERVAL.as_secs()
+        }) {
+            return Err(AppErrorTemplate::BadRequest(None).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RoomPublic {
+    pub id: String,
+    pub name: String,
+    pub mode: RoomMode,
+    pub access: RoomAccess,
+    pub message_max_age: Option<u64>,
+    pub message_max_count: Option<u64>,
+    pub slow_mode_interval: Option<u64>,
+    /// Only shown to moderators, as members could work around the filters knowing them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_filters: Option<Vec<MessageFilterRule>>,
+}
+
+impl RoomPublic {
+    /// Shows a room as seen by `user`
+    pub fn from_room(room: Room, user: &User) -> Self {
+        Self {
+            id: room.id.to_string(),
+            name: room.name,
+            mode: room.mode,
+            access: room.access,
+            message_max_age: room.message_max_age,
+            message_max_count: room.message_max_count,
+            slow_mode_interval: room.slow_mode_interval,
+            message_filters: (user.role >= UserRole::Moderator).then_some(room.message_filters),
+        }
+    }
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize_repr, Serialize_repr, PersistentEmbedded,
+)]
+#[repr(u8)]
+pub enum RoomMode {
+    /// Deleted with its users once the last member leaves
+    #[default]
+    Ephemeral = 0,
+    /// Keeps its users and message history without members
+    Persistent = 1,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize_repr, Serialize_repr, PersistentEmbedded,
+)]
+#[repr(u8)]
+pub enum RoomAccess {
+    #[default]
+    Open = 0,
+    /// Newcomers need the password of the room
+    Password = 1,
+    /// Newcomers need an invite from a member
+    InviteOnly = 2,
+}
+
+/// Single-use code that lets someone into an invite-only room
+#[derive(Clone, Debug, Persistent)]
+pub struct RoomInvite {
+    #[index(mode = "exclusive")]
+    pub code: String,
+    #[index(mode = "cluster")]
+    pub room_id: i64,
+    pub created_by: i64,
+    /// Milliseconds since the UNIX epoch
+    pub expires_at: i64,
+}
+
+impl RoomInvite {
+    pub fn create(room_id: &i64, created_by: &i64) -> Result<Self, AppError> {
+        let database = database::get();
+        let room = Room::find_by_id(room_id)?;
+
+        if room.access != RoomAccess::InviteOnly {
+            return Err(AppErrorTemplate::BadRequest(None).into());
+        }
+
+        let invite = Self {
+            code: nanoid!(),
+            room_id: room.id,
+            created_by: *created_by,
+            expires_at: time::now() + ROOM_INVITE_MAX_AGE.as_millis() as i64,
+        };
+
+        database.rooms().insert_invite(&invite)?;
+
+        Ok(invite)
+    }
+
+    /// Finds an invite to a room that hasn't expired yet
+    pub fn find_valid(code: &str, room_id: &i64) -> Result<Self, AppError> {
+        let database = database::get();
+
+        match database.rooms().find_invite_by_code(code)? {
+            Some(invite) if &invite.room_id == room_id && invite.expires_at > time::now() => {
+                Ok(invite)
+            }
+            _ => Err(AppErrorTemplate::RoomInviteInvalid(None).into()),
+        }
+    }
+
+    pub fn delete(&self) -> Result<(), AppError> {
+        let database = database::get();
+
+        database.rooms().delete_invite(&self.code)
+    }
 }
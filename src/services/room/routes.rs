@@ -0,0 +1,137 @@
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::{self, Path, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use bytes::Bytes;
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{MESSAGE_PAGE_DEFAULT_LIMIT, MESSAGE_PAGE_MAX_LIMIT};
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::message::model::MessagePublic;
+use crate::services::message::search::{self, SearchQuery};
+use crate::services::room::archive::{self, ArchiveFormat};
+use crate::services::room::model::Room;
+use crate::services::session::model::Session;
+use crate::services::user::model::User;
+use crate::utils::snowflake_generator;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportQueryParams {
+    #[serde(default)]
+    format: ArchiveFormat,
+}
+
+pub async fn export(
+    request: HttpRequest,
+    room_name: Path<String>,
+    params: Query<ExportQueryParams>,
+) -> Result<HttpResponse, AppError> {
+    let room = Room::find_by_name(&room_name)?;
+
+    authorize_member(&request, &room)?;
+
+    let file_name = format!("{}.{}", room.name, params.format.extension());
+    let format = params.format;
+    let lines = web::block(move || archive::export(room, format)).await??;
+
+    // The lines are read off the database on the blocking pool, a page of them at a time
+    let chunks = stream::unfold(Some(lines), |lines| async move {
+        let mut lines = lines?;
+        let chunk = web::block(move || {
+            let chunk = lines
+                .by_ref()
+                .take(MESSAGE_PAGE_MAX_LIMIT)
+                .collect::<Result<String, AppError>>()?;
+
+            Ok::<_, AppError>((chunk, lines))
+        })
+        .await
+        .map_err(AppError::from)
+        .and_then(|chunk| chunk);
+
+        match chunk {
+            Ok((chunk, _)) if chunk.is_empty() => None,
+            Ok((chunk, lines)) => Some((Ok(Bytes::from(chunk)), Some(lines))),
+            Err(error) => Some((Err(error), None)),
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(params.format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_name)],
+        })
+        .streaming(chunks))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchQueryParams {
+    query: String,
+    author_id: Option<String>,
+    after: Option<i64>,
+    before: Option<i64>,
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchResponse {
+    messages: Vec<MessagePublic>,
+    cursor: Option<String>,
+}
+
+pub async fn search_messages(
+    request: HttpRequest,
+    room_name: Path<String>,
+    params: Query<SearchQueryParams>,
+) -> Result<HttpResponse, AppError> {
+    let room = Room::find_by_name(&room_name)?;
+
+    let user = authorize_member(&request, &room)?;
+
+    let params = params.into_inner();
+    let query = SearchQuery {
+        text: params.query,
+        author_id: params
+            .author_id
+            .as_deref()
+            .map(snowflake_generator::parse)
+            .transpose()?,
+        after: params.after,
+        before: params.before,
+        cursor: params
+            .cursor
+            .as_deref()
+            .map(snowflake_generator::parse)
+            .transpose()?,
+        limit: params.limit.unwrap_or(MESSAGE_PAGE_DEFAULT_LIMIT),
+    };
+    // Searches scan the messages of the room, which would hold up the other requests of the worker
+    let (messages, cursor) = web::block(move || {
+        let (messages, cursor) = search::search(&room.id, &query)?;
+
+        Ok::<_, AppError>((MessagePublic::from_messages(messages, &user.id)?, cursor))
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SearchResponse {
+        messages,
+        cursor: cursor.map(|cursor| cursor.to_string()),
+    }))
+}
+
+/// Lets through only sessions, passed as a token in the `Authorization` header,
+/// that have a user in the room
+fn authorize_member(request: &HttpRequest, room: &Room) -> Result<User, AppError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|token| token.to_str().ok())
+        .ok_or_else(|| AppError::from(AppErrorTemplate::Unauthorized(None)))?;
+    let session = Session::find_by_token(token)
+        .map_err(|_| AppError::from(AppErrorTemplate::Unauthorized(None)))?;
+
+    User::find_by_session_id_and_room_id(&session.id, &room.id)
+        .map_err(|_| AppErrorTemplate::Forbidden(None).into())
+}
@@ -0,0 +1,128 @@
+use actix::{Context, SystemService};
+
+use crate::error::{AppError, AppErrorTemplate};
+use crate::services::user::model::User;
+use crate::utils::snowflake_generator;
+use crate::web_rtc::actor::WebRtc;
+use crate::web_rtc::connection::WebRtcConnection;
+use crate::web_rtc::message::{TypingMessage, WebRtcMessage, WebRtcMessagePayload};
+
+pub fn patch_read_receipt(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPatchReadReceipt { message_id } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    User::mark_read(
+        &connection.registered_user_id,
+        &snowflake_generator::parse(&message_id)?,
+    )?;
+
+    Ok(())
+}
+
+pub fn patch_status(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPatchUserStatus {
+        status,
+        status_text,
+    } = message.payload
+    else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    User::update_status(&connection.registered_user_id, status, status_text)?;
+
+    Ok(())
+}
+
+pub fn patch_role(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPatchUserRole { user_id, role } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    User::update_role(&snowflake_generator::parse(&user_id)?, &user, role)?;
+
+    Ok(())
+}
+
+pub fn put_mute(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPutUserMute { user_id, duration } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    User::mute(&snowflake_generator::parse(&user_id)?, &user, duration)?;
+
+    Ok(())
+}
+
+pub fn delete_mute(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestDeleteUserMute { user_id } = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    let user = User::find_by_id(&connection.registered_user_id)?;
+
+    User::unmute(&snowflake_generator::parse(&user_id)?, &user)?;
+
+    Ok(())
+}
+
+/// Starts or refreshes the typing indicator, which expires unless refreshed in time
+pub fn put_typing(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestPutTyping = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    WebRtc::from_registry().do_send(TypingMessage {
+        user_id: connection.registered_user_id,
+        room_id: connection.registered_room_id,
+        is_typing: true,
+    });
+
+    Ok(())
+}
+
+pub fn delete_typing(
+    message: WebRtcMessage,
+    connection: &mut WebRtcConnection,
+    _context: &mut Context<WebRtcConnection>,
+) -> Result<(), AppError> {
+    let WebRtcMessagePayload::RequestDeleteTyping = message.payload else {
+        return Err(AppErrorTemplate::BadRequest(None).into());
+    };
+
+    WebRtc::from_registry().do_send(TypingMessage {
+        user_id: connection.registered_user_id,
+        room_id: connection.registered_room_id,
+        is_typing: false,
+    });
+
+    Ok(())
+}
@@ -1,22 +1,18 @@
 use actix::SystemService;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use structsy::derive::{queries, Persistent};
-use structsy::StructsyTx;
+use structsy::derive::{Persistent, PersistentEmbedded};
 
-use crate::constants::{USER_USERNAME_MAX_LENGTH, USER_USERNAME_MIN_LENGTH};
+use crate::constants::{
+    USER_MUTE_MAX_DURATION, USER_STATUS_TEXT_MAX_LENGTH, USER_USERNAME_MAX_LENGTH,
+    USER_USERNAME_MIN_LENGTH,
+};
 use crate::error::{AppError, AppErrorTemplate};
-use crate::utils::snowflake_generator;
+use crate::services::message::model::Message;
+use crate::utils::{snowflake_generator, time};
 use crate::web_rtc::actor::WebRtc;
 use crate::{database, web_rtc};
 
-#[queries(User)]
-trait UserQueries {
-    fn filter_by_id(self, id: i64) -> Self;
-    fn filter_by_username(self, username: String) -> Self;
-    fn filter_by_room_id(self, room_id: i64) -> Self;
-}
-
 #[derive(Clone, Debug, Persistent)]
 pub struct User {
     #[index(mode = "exclusive")]
@@ -24,13 +20,32 @@ pub struct User {
     pub username: String,
     pub room_id: i64,
     pub session_id: i64,
+    pub role: UserRole,
+    /// Newest message of the room the user has read
+    pub last_read_message_id: Option<i64>,
+    /// Status chosen by the user, shown while any of their connections is active
+    pub status: UserStatus,
+    pub status_text: Option<String>,
+    /// Milliseconds since the UNIX epoch until which the user may not post messages
+    pub muted_until: Option<i64>,
+    /// Milliseconds since the UNIX epoch of the last message of the user, for slow mode
+    pub last_message_at: Option<i64>,
     pub active_connection_ids: Vec<i64>,
+    /// Active connections without recent requests
+    pub idle_connection_ids: Vec<i64>,
 }
 
 impl User {
-    pub fn create(username: String, room_id: i64, session_id: i64) -> Result<Self, AppError> {
+    /// Session of users that were imported and haven't been taken by anyone yet
+    pub const UNCLAIMED_SESSION_ID: i64 = 0;
+
+    pub fn create(
+        username: String,
+        room_id: i64,
+        session_id: i64,
+        role: UserRole,
+    ) -> Result<Self, AppError> {
         let database = database::get();
-        let mut transaction = database.begin()?;
 
         // Simulate unique by two columns
         if Self::find_by_username_and_room_id(&username, &room_id).is_ok() {
@@ -42,50 +57,49 @@ impl User {
             username,
             room_id,
             session_id,
+            role,
+            last_read_message_id: None,
+            status: UserStatus::Online,
+            status_text: None,
+            muted_until: None,
+            last_message_at: None,
             active_connection_ids: Vec::new(),
+            idle_connection_ids: Vec::new(),
         };
 
-        transaction.insert(&user)?;
-        transaction.commit()?;
+        database.users().insert(&user)?;
 
         Ok(user)
     }
 
-    pub fn find_all_by_room_id(id: &i64) -> Result<Vec<Self>, AppError> {
+    pub fn find_by_id(id: &i64) -> Result<Self, AppError> {
         let database = database::get();
 
-        let users = database
-            .query::<Self>()
-            .filter_by_room_id(*id)
-            .into_iter()
-            .map(|data| data.1)
-            .collect();
+        if let Some(user) = database.users().find_by_id(id)? {
+            return Ok(user);
+        }
 
-        Ok(users)
+        Err(AppErrorTemplate::NotFound(None).into())
     }
 
-    pub fn delete_by_room_id(id: &i64) -> Result<(), AppError> {
+    pub fn find_all_by_room_id(id: &i64) -> Result<Vec<Self>, AppError> {
         let database = database::get();
-        let mut transaction = database.begin()?;
 
-        for (user_id, _) in database.query::<Self>().filter_by_room_id(*id).into_iter() {
-            transaction.delete(&user_id)?;
-        }
+        database.users().find_all_by_room_id(id)
+    }
 
-        transaction.commit()?;
+    pub fn delete_by_room_id(id: &i64) -> Result<(), AppError> {
+        let database = database::get();
 
-        Ok(())
+        database.users().delete_by_room_id(id)
     }
 
     pub fn find_by_username_and_room_id(username: &str, room_id: &i64) -> Result<Self, AppError> {
         let database = database::get();
 
-        if let Some((_, user)) = database
-            .query::<Self>()
-            .filter_by_username(username.to_string())
-            .filter_by_room_id(*room_id)
-            .into_iter()
-            .next()
+        if let Some(user) = database
+            .users()
+            .find_by_username_and_room_id(username, room_id)?
         {
             return Ok(user);
         }
@@ -93,62 +107,337 @@ impl User {
         Err(AppErrorTemplate::NotFound(None).into())
     }
 
-    pub fn register_connection(id: i64, user_id: &i64) -> Result<(), AppError> {
+    pub fn find_by_session_id_and_room_id(
+        session_id: &i64,
+        room_id: &i64,
+    ) -> Result<Self, AppError> {
         let database = database::get();
 
-        if let Some((user_id, user)) = database
-            .query::<Self>()
-            .filter_by_id(*user_id)
-            .into_iter()
-            .next()
+        if let Some(user) = database
+            .users()
+            .find_by_session_id_and_room_id(session_id, room_id)?
         {
-            let mut transaction = database.begin()?;
-            let mut user = user;
-            let room_id = user.room_id;
+            return Ok(user);
+        }
 
-            user.active_connection_ids.push(id);
-            transaction.update(&user_id, &user)?;
-            transaction.commit()?;
+        Err(AppErrorTemplate::NotFound(None).into())
+    }
 
-            if user.active_connection_ids.len() == 1 {
-                WebRtc::from_registry()
-                    .do_send(web_rtc::message::UserUpdateMessage { user, room_id });
-            }
+    /// Hands an imported user to a session as a member,
+    /// as anyone can claim a username and archived roles would go along with it
+    pub fn claim(id: &i64, session_id: i64) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut user = Self::find_by_id(id)?;
 
-            return Ok(());
+        if user.session_id != Self::UNCLAIMED_SESSION_ID {
+            return Err(AppErrorTemplate::NotFound(None).into());
         }
 
-        Err(AppErrorTemplate::NotFound(None).into())
+        user.session_id = session_id;
+        user.role = UserRole::Member;
+        database.users().update(&user)?;
+
+        Ok(user)
     }
 
-    pub fn unregister_connection(id: &i64, user_id: &i64) -> Result<(), AppError> {
+    /// Changes the role of a member, which only owners can do and not to or from the owner role
+    pub fn update_role(id: &i64, by: &User, role: UserRole) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut user = Self::find_by_id(id)?;
+
+        if user.room_id != by.room_id {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        if by.role != UserRole::Owner || user.role == UserRole::Owner {
+            return Err(AppErrorTemplate::Forbidden(None).into());
+        }
+
+        if role == UserRole::Owner {
+            return Err(AppErrorTemplate::BadRequest(None).into());
+        }
+
+        if user.role == role {
+            return Ok(user);
+        }
+
+        user.role = role;
+        database.users().update(&user)?;
+
+        WebRtc::from_registry().do_send(web_rtc::message::UserUpdateMessage {
+            user: user.clone(),
+            room_id: user.room_id,
+        });
+
+        Ok(user)
+    }
+
+    /// Keeps a user from posting messages for `duration` seconds, which moderators can do
+    /// to members below them
+    pub fn mute(id: &i64, by: &User, duration: u64) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut user = Self::find_by_id(id)?;
+
+        user.check_moderated_by(by)?;
+
+        if duration == 0 || duration > USER_MUTE_MAX_DURATION.as_secs() {
+            return Err(AppErrorTemplate::BadRequest(None).into());
+        }
+
+        let muted_until = time::now() + duration as i64 * 1000;
+
+        user.muted_until = Some(muted_until);
+        database.users().update(&user)?;
+
+        WebRtc::from_registry().do_send(web_rtc::message::UserUpdateMessage {
+            user: user.clone(),
+            room_id: user.room_id,
+        });
+        WebRtc::from_registry().do_send(web_rtc::message::UserMuteMessage {
+            user_id: user.id,
+            muted_until,
+        });
+
+        Ok(user)
+    }
+
+    pub fn unmute(id: &i64, by: &User) -> Result<Self, AppError> {
+        let mut user = Self::find_by_id(id)?;
+
+        user.check_moderated_by(by)?;
+
+        if user.muted_until.is_some() {
+            user.clear_mute()?;
+        }
+
+        Ok(user)
+    }
+
+    /// Lifts the mute of a user once it is over, the timer may be outdated by a newer mute
+    pub fn expire_mute(id: &i64) -> Result<(), AppError> {
+        let database = database::get();
+
+        // The user may be gone with their ephemeral room
+        let Some(mut user) = database.users().find_by_id(id)? else {
+            return Ok(());
+        };
+
+        match user.muted_until {
+            Some(muted_until) if muted_until <= time::now() => user.clear_mute(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Finds users whose mute has to be lifted later, e.g. after a restart
+    pub fn find_all_muted() -> Result<Vec<Self>, AppError> {
         let database = database::get();
 
-        if let Some((user_id, user)) = database
-            .query::<Self>()
-            .filter_by_id(*user_id)
+        Ok(database
+            .users()
+            .find_all()?
             .into_iter()
-            .next()
+            .filter(|user| user.muted_until.is_some())
+            .collect())
+    }
+
+    fn clear_mute(&mut self) -> Result<(), AppError> {
+        let database = database::get();
+
+        self.muted_until = None;
+        database.users().update(self)?;
+
+        WebRtc::from_registry().do_send(web_rtc::message::UserUpdateMessage {
+            user: self.clone(),
+            room_id: self.room_id,
+        });
+
+        Ok(())
+    }
+
+    /// Checks that `by` is a moderator of the room of the user and ranks above them
+    pub fn check_moderated_by(&self, by: &User) -> Result<(), AppError> {
+        if self.room_id != by.room_id {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        if by.role < UserRole::Moderator || self.role >= by.role {
+            return Err(AppErrorTemplate::Forbidden(None).into());
+        }
+
+        Ok(())
+    }
+
+    pub fn check_muted(&self) -> Result<(), AppError> {
+        match self
+            .muted_until
+            .is_some_and(|muted_until| muted_until > time::now())
+        {
+            true => Err(AppErrorTemplate::UserMuted(None).into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Sets when a user last posted unless they already did after `since`,
+    /// returns whether it was set
+    pub fn update_last_message_at(
+        id: &i64,
+        last_message_at: i64,
+        since: Option<i64>,
+    ) -> Result<bool, AppError> {
+        let database = database::get();
+
+        database
+            .users()
+            .update_last_message_at(id, last_message_at, since)
+    }
+
+    /// Moves the read position of a user forward to a message of their room
+    pub fn mark_read(id: &i64, message_id: &i64) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut user = Self::find_by_id(id)?;
+        let message = Message::find_by_id(message_id)?;
+
+        if message.room_id != user.room_id {
+            return Err(AppErrorTemplate::NotFound(None).into());
+        }
+
+        if user
+            .last_read_message_id
+            .is_some_and(|last_read_message_id| last_read_message_id >= message.id)
         {
-            let mut transaction = database.begin()?;
-            let mut user = user;
-            let room_id = user.room_id;
+            return Ok(user);
+        }
+
+        user.last_read_message_id = Some(message.id);
+        database.users().update(&user)?;
+
+        WebRtc::from_registry().do_send(web_rtc::message::ReadReceiptMessage {
+            user: user.clone(),
+            room_id: user.room_id,
+        });
+
+        Ok(user)
+    }
+
+    /// Counts the messages of others posted after the read position of the user
+    pub fn count_unread_messages(&self) -> Result<usize, AppError> {
+        let database = database::get();
+
+        database
+            .messages()
+            .count_by_room_id(&self.room_id, self.last_read_message_id, &self.id)
+    }
+
+    pub fn update_status(
+        id: &i64,
+        status: UserStatus,
+        status_text: Option<String>,
+    ) -> Result<Self, AppError> {
+        let database = database::get();
+        let mut user = Self::find_by_id(id)?;
+        let status_text = status_text
+            .map(|status_text| status_text.trim().to_owned())
+            .filter(|status_text| !status_text.is_empty());
+
+        if status == UserStatus::Offline {
+            return Err(AppErrorTemplate::BadRequest(None).into());
+        }
+
+        if let Some(status_text) = &status_text {
+            Self::check_status_text_length(status_text)?;
+        }
+
+        if user.status == status && user.status_text == status_text {
+            return Ok(user);
+        }
 
+        user.status = status;
+        user.status_text = status_text;
+        database.users().update(&user)?;
+
+        WebRtc::from_registry().do_send(web_rtc::message::UserUpdateMessage {
+            user: user.clone(),
+            room_id: user.room_id,
+        });
+
+        Ok(user)
+    }
+
+    pub fn register_connection(id: i64, user_id: &i64) -> Result<(), AppError> {
+        Self::update_connections(user_id, |user| user.active_connection_ids.push(id))
+    }
+
+    pub fn unregister_connection(id: &i64, user_id: &i64) -> Result<(), AppError> {
+        Self::update_connections(user_id, |user| {
             user.active_connection_ids
-                .retain(|&connection_id| &connection_id != id);
+                .retain(|connection_id| connection_id != id);
+            user.idle_connection_ids
+                .retain(|connection_id| connection_id != id);
+        })
+    }
+
+    /// Marks a connection as idle after a while without requests, or as active again
+    pub fn set_connection_idle(id: &i64, user_id: &i64, is_idle: bool) -> Result<(), AppError> {
+        Self::update_connections(user_id, |user| {
+            user.idle_connection_ids
+                .retain(|connection_id| connection_id != id);
+
+            if is_idle && user.active_connection_ids.contains(id) {
+                user.idle_connection_ids.push(*id);
+            }
+        })
+    }
+
+    /// Saves changes to the connections of a user and lets the room know if they changed the status
+    fn update_connections(user_id: &i64, update: impl FnOnce(&mut Self)) -> Result<(), AppError> {
+        let database = database::get();
+        let mut user = Self::find_by_id(user_id)?;
+        let previous_status = user.get_public_status();
+
+        update(&mut user);
+        database.users().update(&user)?;
+
+        if user.get_public_status() != previous_status {
+            WebRtc::from_registry().do_send(web_rtc::message::UserUpdateMessage {
+                user: user.clone(),
+                room_id: user.room_id,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Status as others see it: offline without connections, idle when only idle ones are left
+    pub fn get_public_status(&self) -> UserStatus {
+        if self.active_connection_ids.is_empty() {
+            return UserStatus::Offline;
+        }
+
+        let is_idle = self
+            .active_connection_ids
+            .iter()
+            .all(|connection_id| self.idle_connection_ids.contains(connection_id));
 
-            transaction.update(&user_id, &user)?;
-            transaction.commit()?;
+        match self.status {
+            UserStatus::Online if is_idle => UserStatus::Idle,
+            status => status,
+        }
+    }
 
+    pub fn reset_active_connections() -> Result<(), AppError> {
+        let database = database::get();
+
+        for mut user in database.users().find_all()? {
             if user.active_connection_ids.is_empty() {
-                WebRtc::from_registry()
-                    .do_send(web_rtc::message::UserUpdateMessage { user, room_id });
+                continue;
             }
 
-            return Ok(());
+            user.active_connection_ids.clear();
+            user.idle_connection_ids.clear();
+            database.users().update(&user)?;
         }
 
-        Err(AppErrorTemplate::NotFound(None).into())
+        Ok(())
     }
 
     pub fn check_username_length(username: &str) -> Result<(), AppError> {
@@ -164,32 +453,78 @@ impl User {
             _ => Ok(()),
         }
     }
+
+    pub fn check_status_text_length(status_text: &str) -> Result<(), AppError> {
+        match status_text.chars().count() > USER_STATUS_TEXT_MAX_LENGTH {
+            true => Err(AppErrorTemplate::UserStatusTextTooLong(None).into()),
+            false => Ok(()),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 pub struct UserPublic {
     pub id: String,
     pub username: String,
+    pub role: UserRole,
     pub status: UserStatus,
+    pub status_text: Option<String>,
+    pub muted_until: Option<i64>,
+    pub last_read_message_id: Option<String>,
 }
 
 impl From<User> for UserPublic {
     fn from(user: User) -> Self {
+        let status = user.get_public_status();
+
         Self {
             id: user.id.to_string(),
             username: user.username,
-            status: match user.active_connection_ids.len() {
-                0 => UserStatus::Offline,
-                _ => UserStatus::Online,
-            },
+            role: user.role,
+            status,
+            status_text: user.status_text,
+            muted_until: user.muted_until,
+            last_read_message_id: user
+                .last_read_message_id
+                .map(|last_read_message_id| last_read_message_id.to_string()),
         }
     }
 }
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize_repr, Serialize_repr)]
+/// Roles are ordered, so that checks like `role >= UserRole::Moderator` include owners
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Deserialize_repr,
+    Serialize_repr,
+    PersistentEmbedded,
+)]
+#[repr(u8)]
+pub enum UserRole {
+    #[default]
+    Member = 0,
+    /// Can redact messages of anyone in the room and change its settings
+    Moderator = 1,
+    /// Created the room, can also promote and demote moderators
+    Owner = 2,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize_repr, Serialize_repr, PersistentEmbedded,
+)]
 #[repr(u8)]
 pub enum UserStatus {
     #[default]
     Offline = 0,
     Online = 1,
+    /// Also shown for online users while all of their connections are idle
+    Idle = 2,
+    Away = 3,
+    DoNotDisturb = 4,
 }
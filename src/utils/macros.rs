@@ -30,6 +30,12 @@ macro_rules! payload_enum_helper {
 
                 Ok(())
             }
+
+            pub fn get_type(&self) -> u32 {
+                match self {
+                    $($name::$variant $({ $($field: _),* })? => $discriminant_expr),+
+                }
+            }
         }
 
         paste! {
@@ -1,2 +1,5 @@
 pub mod macros;
+pub mod password;
+pub mod rate_limiter;
 pub mod snowflake_generator;
+pub mod time;
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+use crate::error::{AppError, AppErrorTemplate};
+
+/// Opcode, and payload type for limits that only count one kind of request
+type RateLimitKey = (u8, Option<u32>);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// Requests that can be made at once, also refilled evenly over `period`
+    pub requests: u32,
+    pub period: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits(HashMap<RateLimitKey, RateLimit>);
+
+impl RateLimits {
+    /// Parses comma separated `<opcode>[:<payload type>]=<requests>/<seconds>` entries,
+    /// e.g. `1=20/10,1:10=5/60`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut limits = HashMap::new();
+
+        for entry in value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let invalid = || format!("Invalid rate limit: {entry}");
+            let (key, limit) = entry.split_once('=').ok_or_else(invalid)?;
+            let (opcode, payload_type) = match key.split_once(':') {
+                Some((opcode, payload_type)) => (opcode, Some(payload_type)),
+                None => (key, None),
+            };
+            let (requests, seconds) = limit.split_once('/').ok_or_else(invalid)?;
+
+            let opcode = opcode.trim().parse().map_err(|_| invalid())?;
+            let payload_type = payload_type
+                .map(|payload_type| payload_type.trim().parse())
+                .transpose()
+                .map_err(|_| invalid())?;
+            let requests = requests.trim().parse().map_err(|_| invalid())?;
+            let seconds = seconds.trim().parse().map_err(|_| invalid())?;
+
+            if requests == 0 || seconds == 0 {
+                return Err(invalid());
+            }
+
+            limits.insert(
+                (opcode, payload_type),
+                RateLimit {
+                    requests,
+                    period: Duration::from_secs(seconds),
+                },
+            );
+        }
+
+        Ok(Self(limits))
+    }
+
+    /// Reads limits from an environment variable, whose entries replace the defaults they match.
+    ///
+    /// Panics on an invalid value, limits shouldn't silently fall back to the defaults.
+    pub fn from_env(name: &str, defaults: &str) -> Self {
+        let mut limits = Self::parse(defaults).expect("Invalid default rate limits");
+
+        if let Ok(value) = env::var(name) {
+            let overrides = Self::parse(&value).unwrap_or_else(|error| panic!("{name}: {error}"));
+
+            limits.0.extend(overrides.0);
+        }
+
+        limits
+    }
+
+    fn get(&self, key: &RateLimitKey) -> Option<&RateLimit> {
+        self.0.get(key)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.requests as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Adds the tokens earned since the last refill, returns how long until one is available
+    fn refill(&mut self, limit: &RateLimit, now: Instant) -> Duration {
+        let rate = limit.requests as f64 / limit.period.as_secs_f64();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate).min(limit.requests as f64);
+        self.refilled_at = now;
+
+        match self.tokens >= 1.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64((1.0 - self.tokens) / rate),
+        }
+    }
+}
+
+/// Token buckets of one connection
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<RateLimitKey, TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Takes a token for the opcode and one for the payload type if they are limited,
+    /// or none of them when either has run out
+    pub fn check(
+        &mut self,
+        limits: &RateLimits,
+        opcode: u8,
+        payload_type: u32,
+    ) -> Result<(), AppError> {
+        self.check_at(limits, opcode, payload_type, Instant::now())
+    }
+
+    fn check_at(
+        &mut self,
+        limits: &RateLimits,
+        opcode: u8,
+        payload_type: u32,
+        now: Instant,
+    ) -> Result<(), AppError> {
+        let keys = [(opcode, None), (opcode, Some(payload_type))];
+        let mut retry_after = Duration::ZERO;
+
+        for key in keys {
+            let Some(limit) = limits.get(&key) else {
+                continue;
+            };
+            let bucket = self
+                .buckets
+                .entry(key)
+                .or_insert_with(|| TokenBucket::new(limit, now));
+
+            retry_after = retry_after.max(bucket.refill(limit, now));
+        }
+
+        if !retry_after.is_zero() {
+            let retry_after = (retry_after.as_secs_f64() * 1000.0).ceil() as u64;
+
+            return Err(
+                AppError::from(AppErrorTemplate::RateLimited(None)).with_retry_after(retry_after)
+            );
+        }
+
+        for key in keys {
+            if let Some(bucket) = self.buckets.get_mut(&key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_opcode_and_payload_type_limits() {
+        let limits = RateLimits::parse(" 1=20/10, 1:10=5/60,").unwrap();
+
+        assert_eq!(
+            limits.get(&(1, None)),
+            Some(&RateLimit {
+                requests: 20,
+                period: Duration::from_secs(10),
+            })
+        );
+        assert_eq!(
+            limits.get(&(1, Some(10))),
+            Some(&RateLimit {
+                requests: 5,
+                period: Duration::from_secs(60),
+            })
+        );
+        assert_eq!(limits.get(&(2, None)), None);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_entries() {
+        for value in [
+            "1",
+            "1=20",
+            "=20/10",
+            "a=20/10",
+            "1:b=20/10",
+            "1=a/10",
+            "1=20/b",
+            "1=0/10",
+            "1=20/0",
+            "256=20/10",
+            "1=20/10,2",
+        ] {
+            assert!(RateLimits::parse(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn check_drains_and_refills_the_bucket() {
+        let limits = RateLimits::parse("1=2/10").unwrap();
+        let mut rate_limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        assert!(rate_limiter.check_at(&limits, 1, 0, now).is_ok());
+        assert!(rate_limiter.check_at(&limits, 1, 0, now).is_ok());
+
+        let error = rate_limiter.check_at(&limits, 1, 0, now).unwrap_err();
+
+        assert_eq!(error.http_code, 429);
+        // A token comes back every five seconds
+        assert_eq!(error.retry_after, Some(5000));
+
+        let error = rate_limiter
+            .check_at(&limits, 1, 0, now + Duration::from_secs(4))
+            .unwrap_err();
+
+        assert_eq!(error.retry_after, Some(1000));
+        assert!(rate_limiter
+            .check_at(&limits, 1, 0, now + Duration::from_secs(5))
+            .is_ok());
+        assert!(rate_limiter
+            .check_at(&limits, 1, 0, now + Duration::from_secs(5))
+            .is_err());
+
+        // Unlimited opcodes are let through
+        assert!(rate_limiter.check_at(&limits, 2, 0, now).is_ok());
+    }
+
+    #[test]
+    fn check_takes_no_token_when_the_payload_type_has_run_out() {
+        let limits = RateLimits::parse("1=3/10,1:10=1/10").unwrap();
+        let mut rate_limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        assert!(rate_limiter.check_at(&limits, 1, 10, now).is_ok());
+        assert!(rate_limiter.check_at(&limits, 1, 10, now).is_err());
+        assert!(rate_limiter.check_at(&limits, 1, 11, now).is_ok());
+        assert!(rate_limiter.check_at(&limits, 1, 11, now).is_ok());
+        assert!(rate_limiter.check_at(&limits, 1, 11, now).is_err());
+    }
+}
@@ -4,12 +4,16 @@ use std::time::{Duration, UNIX_EPOCH};
 use lazy_static::lazy_static;
 use snowflake::SnowflakeIdGenerator;
 
+use crate::error::{AppError, AppErrorTemplate};
+
+const EPOCH: u64 = 1696118400000;
+
 lazy_static! {
     static ref SNOWFLAKE_ID_GENERATOR: Mutex<SnowflakeIdGenerator> = {
         Mutex::new(SnowflakeIdGenerator::with_epoch(
             0,
             0,
-            UNIX_EPOCH + Duration::from_millis(1696118400000),
+            UNIX_EPOCH + Duration::from_millis(EPOCH),
         ))
     };
 }
@@ -18,6 +22,26 @@ pub fn generate() -> i64 {
     SNOWFLAKE_ID_GENERATOR.lock().unwrap().real_time_generate()
 }
 
+/// Parses an ID that was sent to a client as a string
+pub fn parse(id: &str) -> Result<i64, AppError> {
+    id.parse()
+        .map_err(|_| AppErrorTemplate::BadRequest(None).into())
+}
+
+/// Milliseconds since the UNIX epoch at which the ID was generated
+pub fn timestamp(id: i64) -> i64 {
+    (id >> 22) + EPOCH as i64
+}
+
+/// Smallest ID that could be generated at the given milliseconds since the UNIX epoch,
+/// clamped to the IDs that fit into an `i64`
+pub fn from_timestamp(timestamp: i64) -> i64 {
+    timestamp
+        .saturating_sub(EPOCH as i64)
+        .clamp(0, i64::MAX >> 22)
+        << 22
+}
+
 pub fn init() {
     info!("Initialize Snowflake Generator");
 
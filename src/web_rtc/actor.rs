@@ -1,23 +1,53 @@
 use std::collections::HashMap;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use actix::{
     Actor, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner, Handler, Message,
     Running, Supervised, SystemService, WrapFuture,
 };
+use lazy_static::lazy_static;
 
+use crate::constants::{
+    WEB_RTC_RATE_LIMITS, WEB_RTC_TYPING_EXPIRY_INTERVAL, WEB_RTC_TYPING_TIMEOUT,
+};
 use crate::error::{AppError, AppErrorTemplate};
-use crate::services::message;
+use crate::services::direct_message::model::DirectMessagePublic;
+use crate::services::message::model::{MessagePublic, MessageReaction, MessageReactionPublic};
+use crate::services::room::model::RoomPublic;
 use crate::services::user::model::User;
+use crate::services::{direct_message, message, room, user};
+use crate::utils::rate_limiter::RateLimits;
+use crate::utils::time;
 use crate::web_rtc::connection::WebRtcConnection;
 use crate::web_rtc::message::{
-    DisconnectionMessage, MessageUpdateMessage, Opcode, RegistrationMessage, UserUpdateMessage,
+    CloseConnectionMessage, DirectMessageUpdateMessage, DisconnectionMessage, MessageDeleteMessage,
+    MessageMentionMessage, MessageReactionUpdateMessage, MessageUpdateMessage, Opcode,
+    ReadReceiptMessage, RegistrationMessage, RoomUpdateMessage, TypingMessage, UserKickMessage,
+    UserMuteMessage, UserUpdateMessage,
 };
 use crate::web_rtc::message::{WebRtcMessage, WebRtcMessagePayload};
 
+lazy_static! {
+    static ref RATE_LIMITS: RateLimits =
+        RateLimits::from_env("MESSENGER_WEB_RTC_RATE_LIMITS", WEB_RTC_RATE_LIMITS);
+}
+
+/// Reads the rate limits up front, so that an invalid configuration stops the startup
+pub fn init_rate_limits() {
+    lazy_static::initialize(&RATE_LIMITS);
+}
+
 #[derive(Debug, Default)]
 pub struct WebRtc {
     connections: HashMap<i64, Addr<WebRtcConnection>>,
+    /// Users currently typing, by their IDs
+    typing_users: HashMap<i64, TypingUser>,
+}
+
+#[derive(Debug)]
+struct TypingUser {
+    room_id: i64,
+    expires_at: Instant,
 }
 
 impl WebRtc {
@@ -26,6 +56,12 @@ impl WebRtc {
         message: WebRtcMessage,
         context: &mut Context<WebRtcConnection>,
     ) -> Result<(), AppError> {
+        connection.rate_limiter.check(
+            &RATE_LIMITS,
+            message.opcode as u8,
+            message.payload.get_type(),
+        )?;
+
         match message.opcode {
             Opcode::HeartBeat => {
                 connection.last_heartbeat_at = Instant::now();
@@ -40,10 +76,67 @@ impl WebRtc {
                 WebRtc::send_message(message.id, response, connection, context);
             }
             Opcode::Request => {
+                connection.set_idle(false);
+
                 let handle = match message.payload {
                     WebRtcMessagePayload::RequestPostMessage { .. } => {
                         message::handlers::post_message
                     }
+                    WebRtcMessagePayload::RequestGetMessages { .. } => {
+                        message::handlers::get_messages
+                    }
+                    WebRtcMessagePayload::RequestPatchRoomRetention { .. } => {
+                        room::handlers::patch_retention
+                    }
+                    WebRtcMessagePayload::RequestGetMessageSearch { .. } => {
+                        message::handlers::search_messages
+                    }
+                    WebRtcMessagePayload::RequestPatchMessage { .. } => {
+                        message::handlers::patch_message
+                    }
+                    WebRtcMessagePayload::RequestGetMessageRevisions { .. } => {
+                        message::handlers::get_message_revisions
+                    }
+                    WebRtcMessagePayload::RequestDeleteMessage { .. } => {
+                        message::handlers::delete_message
+                    }
+                    WebRtcMessagePayload::RequestPutMessageReaction { .. } => {
+                        message::handlers::put_message_reaction
+                    }
+                    WebRtcMessagePayload::RequestDeleteMessageReaction { .. } => {
+                        message::handlers::delete_message_reaction
+                    }
+                    WebRtcMessagePayload::RequestGetThreadMessages { .. } => {
+                        message::handlers::get_thread_messages
+                    }
+                    WebRtcMessagePayload::RequestPatchReadReceipt { .. } => {
+                        user::handlers::patch_read_receipt
+                    }
+                    WebRtcMessagePayload::RequestPutTyping => user::handlers::put_typing,
+                    WebRtcMessagePayload::RequestDeleteTyping => user::handlers::delete_typing,
+                    WebRtcMessagePayload::RequestPatchUserStatus { .. } => {
+                        user::handlers::patch_status
+                    }
+                    WebRtcMessagePayload::RequestPostDirectMessage { .. } => {
+                        direct_message::handlers::post_direct_message
+                    }
+                    WebRtcMessagePayload::RequestGetDirectMessages { .. } => {
+                        direct_message::handlers::get_direct_messages
+                    }
+                    WebRtcMessagePayload::RequestPostRoomInvite => room::handlers::post_invite,
+                    WebRtcMessagePayload::RequestPatchUserRole { .. } => user::handlers::patch_role,
+                    WebRtcMessagePayload::RequestPostRoomKick { .. } => room::handlers::post_kick,
+                    WebRtcMessagePayload::RequestPutRoomBan { .. } => room::handlers::put_ban,
+                    WebRtcMessagePayload::RequestPutUserMute { .. } => user::handlers::put_mute,
+                    WebRtcMessagePayload::RequestDeleteUserMute { .. } => {
+                        user::handlers::delete_mute
+                    }
+                    WebRtcMessagePayload::RequestPatchRoomSlowMode { .. } => {
+                        room::handlers::patch_slow_mode
+                    }
+                    WebRtcMessagePayload::RequestPatchRoomMessageFilters { .. } => {
+                        room::handlers::patch_message_filters
+                    }
                     // Other
                     _ => return Err(AppErrorTemplate::BadRequest(None).into()),
                 };
@@ -81,6 +174,7 @@ impl WebRtc {
                         payload: WebRtcMessagePayload::Response {
                             code: error.json_code,
                             message: error.get_safe_message(),
+                            retry_after: error.retry_after,
                         },
                     });
                 }
@@ -88,6 +182,69 @@ impl WebRtc {
             .spawn(context);
     }
 
+    /// Lets the other members of a room know whether a user is typing
+    fn dispatch_typing(
+        &self,
+        user_id: &i64,
+        room_id: &i64,
+        is_typing: bool,
+    ) -> Result<(), AppError> {
+        let users = User::find_all_by_room_id(room_id)?;
+
+        for connection_id in users
+            .iter()
+            .filter(|user| &user.id != user_id)
+            .flat_map(|user| &user.active_connection_ids)
+        {
+            let Ok(connection) = self.get_connection(connection_id) else {
+                continue;
+            };
+
+            let message = WebRtcMessage {
+                id: -1,
+                connection_id: *connection_id,
+                opcode: Opcode::Dispatch,
+                payload: WebRtcMessagePayload::DispatchTypingUpdate {
+                    user_id: user_id.to_string(),
+                    is_typing,
+                },
+            };
+
+            connection.do_send(message);
+        }
+
+        Ok(())
+    }
+
+    /// Stops typing indicators that weren't refreshed, e.g. because the stop request got lost
+    fn expire_typing(&mut self) -> Result<(), AppError> {
+        let now = Instant::now();
+        let expired: Vec<(i64, i64)> = self
+            .typing_users
+            .iter()
+            .filter(|(_, typing_user)| typing_user.expires_at <= now)
+            .map(|(user_id, typing_user)| (*user_id, typing_user.room_id))
+            .collect();
+
+        for (user_id, room_id) in expired {
+            self.typing_users.remove(&user_id);
+            self.dispatch_typing(&user_id, &room_id, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lifts a mute once it is over, the timer doesn't survive a restart so it's set again then
+    fn schedule_mute_expiry(user_id: i64, muted_until: i64, context: &mut Context<Self>) {
+        let delay = Duration::from_millis((muted_until - time::now()).max(0) as u64);
+
+        context.run_later(delay, move |_, _| {
+            if let Err(error) = User::expire_mute(&user_id) {
+                error!("Failed to expire mute of user {user_id}: {error}");
+            }
+        });
+    }
+
     fn get_connection(&self, id: &i64) -> Result<&Addr<WebRtcConnection>, AppError> {
         match self.connections.get(id) {
             Some(connection) => Ok(connection),
@@ -116,6 +273,25 @@ impl SystemService for WebRtc {
 impl Actor for WebRtc {
     type Context = Context<Self>;
 
+    fn started(&mut self, context: &mut Self::Context) {
+        context.run_interval(WEB_RTC_TYPING_EXPIRY_INTERVAL, |actor, _| {
+            if let Err(error) = actor.expire_typing() {
+                error!("Failed to expire typing indicators: {error}");
+            }
+        });
+
+        match User::find_all_muted() {
+            Ok(users) => {
+                for user in users {
+                    if let Some(muted_until) = user.muted_until {
+                        Self::schedule_mute_expiry(user.id, muted_until, context);
+                    }
+                }
+            }
+            Err(error) => error!("Failed to schedule mute expiries: {error}"),
+        }
+    }
+
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         let connections_length = self.connections.len();
 
@@ -177,11 +353,133 @@ impl Handler<UserUpdateMessage> for WebRtc {
     }
 }
 
+impl Handler<ReadReceiptMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(&mut self, message: ReadReceiptMessage, _: &mut Context<Self>) -> Self::Result {
+        let users = User::find_all_by_room_id(&message.room_id)?;
+
+        // The reader's own connections get it too, to sync the position across their devices
+        for connection_id in users.iter().flat_map(|user| &user.active_connection_ids) {
+            let Ok(connection) = self.get_connection(connection_id) else {
+                continue;
+            };
+
+            let message = WebRtcMessage {
+                id: -1,
+                connection_id: *connection_id,
+                opcode: Opcode::Dispatch,
+                payload: WebRtcMessagePayload::DispatchReadReceipt {
+                    user_id: message.user.id.to_string(),
+                    message_id: message
+                        .user
+                        .last_read_message_id
+                        .unwrap_or_default()
+                        .to_string(),
+                },
+            };
+
+            connection.do_send(message);
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<TypingMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(&mut self, message: TypingMessage, _: &mut Context<Self>) -> Self::Result {
+        match message.is_typing {
+            true => {
+                self.typing_users.insert(
+                    message.user_id,
+                    TypingUser {
+                        room_id: message.room_id,
+                        expires_at: Instant::now() + WEB_RTC_TYPING_TIMEOUT,
+                    },
+                );
+            }
+            false => {
+                if self.typing_users.remove(&message.user_id).is_none() {
+                    return Ok(());
+                }
+            }
+        }
+
+        // Starts are sent again on every refresh, in case the previous one got lost
+        self.dispatch_typing(&message.user_id, &message.room_id, message.is_typing)
+    }
+}
+
+impl Handler<RoomUpdateMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(&mut self, message: RoomUpdateMessage, _: &mut Context<Self>) -> Self::Result {
+        let users = User::find_all_by_room_id(&message.room.id)?;
+
+        for user in &users {
+            for connection_id in &user.active_connection_ids {
+                let Ok(connection) = self.get_connection(connection_id) else {
+                    continue;
+                };
+
+                let message = WebRtcMessage {
+                    id: -1,
+                    connection_id: *connection_id,
+                    opcode: Opcode::Dispatch,
+                    payload: WebRtcMessagePayload::DispatchRoomUpdate {
+                        room: RoomPublic::from_room(message.room.clone(), user),
+                    },
+                };
+
+                connection.do_send(message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl Handler<MessageUpdateMessage> for WebRtc {
     type Result = Result<(), AppError>;
 
     fn handle(&mut self, message: MessageUpdateMessage, _: &mut Context<Self>) -> Self::Result {
         let users = User::find_all_by_room_id(&message.room_id)?;
+        let reactions = MessageReaction::find_all_by_message_id(&message.message.id)?;
+        let reply_count = message.message.count_replies()?;
+
+        for user in &users {
+            let public =
+                MessagePublic::new(message.message.clone(), &reactions, reply_count, &user.id);
+
+            for connection_id in &user.active_connection_ids {
+                let Ok(connection) = self.get_connection(connection_id) else {
+                    continue;
+                };
+
+                let message = WebRtcMessage {
+                    id: -1,
+                    connection_id: *connection_id,
+                    opcode: Opcode::Dispatch,
+                    payload: WebRtcMessagePayload::DispatchMessageUpdate {
+                        message: public.clone(),
+                    },
+                };
+
+                connection.do_send(message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<MessageDeleteMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(&mut self, message: MessageDeleteMessage, _: &mut Context<Self>) -> Self::Result {
+        let users = User::find_all_by_room_id(&message.room_id)?;
 
         for connection_id in users.iter().flat_map(|user| &user.active_connection_ids) {
             let Ok(connection) = self.get_connection(connection_id) else {
@@ -192,7 +490,7 @@ impl Handler<MessageUpdateMessage> for WebRtc {
                 id: -1,
                 connection_id: *connection_id,
                 opcode: Opcode::Dispatch,
-                payload: WebRtcMessagePayload::DispatchMessageUpdate {
+                payload: WebRtcMessagePayload::DispatchMessageDelete {
                     message: message.message.clone().into(),
                 },
             };
@@ -204,6 +502,141 @@ impl Handler<MessageUpdateMessage> for WebRtc {
     }
 }
 
+impl Handler<MessageMentionMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(&mut self, message: MessageMentionMessage, _: &mut Context<Self>) -> Self::Result {
+        let reactions = MessageReaction::find_all_by_message_id(&message.message.id)?;
+        let reply_count = message.message.count_replies()?;
+
+        for user_id in &message.user_ids {
+            let Ok(user) = User::find_by_id(user_id) else {
+                continue;
+            };
+            let public =
+                MessagePublic::new(message.message.clone(), &reactions, reply_count, &user.id);
+
+            for connection_id in &user.active_connection_ids {
+                let Ok(connection) = self.get_connection(connection_id) else {
+                    continue;
+                };
+
+                let message = WebRtcMessage {
+                    id: -1,
+                    connection_id: *connection_id,
+                    opcode: Opcode::Dispatch,
+                    payload: WebRtcMessagePayload::DispatchMessageMention {
+                        message: public.clone(),
+                    },
+                };
+
+                connection.do_send(message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<DirectMessageUpdateMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(
+        &mut self,
+        message: DirectMessageUpdateMessage,
+        _: &mut Context<Self>,
+    ) -> Self::Result {
+        let public = DirectMessagePublic::from(message.message.clone());
+
+        for user_id in [message.message.author_id, message.message.recipient_id] {
+            let Ok(user) = User::find_by_id(&user_id) else {
+                continue;
+            };
+
+            for connection_id in &user.active_connection_ids {
+                let Ok(connection) = self.get_connection(connection_id) else {
+                    continue;
+                };
+
+                let message = WebRtcMessage {
+                    id: -1,
+                    connection_id: *connection_id,
+                    opcode: Opcode::Dispatch,
+                    payload: WebRtcMessagePayload::DispatchDirectMessageUpdate {
+                        message: public.clone(),
+                    },
+                };
+
+                connection.do_send(message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<MessageReactionUpdateMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(
+        &mut self,
+        message: MessageReactionUpdateMessage,
+        _: &mut Context<Self>,
+    ) -> Self::Result {
+        let users = User::find_all_by_room_id(&message.room_id)?;
+
+        for user in &users {
+            let reactions = MessageReactionPublic::aggregate(&message.reactions, &user.id);
+
+            for connection_id in &user.active_connection_ids {
+                let Ok(connection) = self.get_connection(connection_id) else {
+                    continue;
+                };
+
+                let message = WebRtcMessage {
+                    id: -1,
+                    connection_id: *connection_id,
+                    opcode: Opcode::Dispatch,
+                    payload: WebRtcMessagePayload::DispatchMessageReactionUpdate {
+                        message_id: message.message_id.to_string(),
+                        reactions: reactions.clone(),
+                    },
+                };
+
+                connection.do_send(message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<UserMuteMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(&mut self, message: UserMuteMessage, context: &mut Context<Self>) -> Self::Result {
+        Self::schedule_mute_expiry(message.user_id, message.muted_until, context);
+
+        Ok(())
+    }
+}
+
+impl Handler<UserKickMessage> for WebRtc {
+    type Result = Result<(), AppError>;
+
+    fn handle(&mut self, message: UserKickMessage, _: &mut Context<Self>) -> Self::Result {
+        for connection_id in &message.connection_ids {
+            let Ok(connection) = self.get_connection(connection_id) else {
+                continue;
+            };
+
+            connection.do_send(CloseConnectionMessage);
+        }
+
+        Ok(())
+    }
+}
+
 impl Handler<DisconnectionMessage> for WebRtc {
     type Result = Result<(), AppError>;
 
@@ -1,17 +1,18 @@
+use std::future::{self, Future};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use actix::{
     Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner,
     Handler, Running, SystemService, WrapFuture,
 };
-use actix_web::rt::time;
 use educe::Educe;
 use webrtc::api::interceptor_registry::register_default_interceptors;
 use webrtc::api::media_engine::MediaEngine;
 use webrtc::api::setting_engine::SettingEngine;
 use webrtc::api::APIBuilder;
 use webrtc::data::data_channel::DataChannel;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::interceptor::registry::Registry;
 use webrtc::peer_connection::configuration::RTCConfiguration;
@@ -19,11 +20,14 @@ use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::RTCPeerConnection;
 
 use crate::constants::{
-    WEB_RTC_CLIENT_TIMEOUT, WEB_RTC_DATA_CHANNEL_BUFFER_SIZE, WEB_RTC_HEARTBEAT_INTERVAL,
+    MESSAGE_PAGE_DEFAULT_LIMIT, WEB_RTC_CLIENT_TIMEOUT, WEB_RTC_DATA_CHANNEL_BUFFER_SIZE,
+    WEB_RTC_EPHEMERAL_DATA_CHANNEL_LABEL, WEB_RTC_HEARTBEAT_INTERVAL, WEB_RTC_IDLE_TIMEOUT,
 };
 use crate::error::{AppError, AppErrorTemplate};
-use crate::services::message::model::Message;
+use crate::services::message::model::{Message, MessagePublic};
+use crate::services::room::model::{Room, RoomPublic};
 use crate::services::user::model::User;
+use crate::utils::rate_limiter::RateLimiter;
 use crate::web_rtc::actor::WebRtc;
 use crate::web_rtc::message::{
     CloseConnectionMessage, DisconnectionMessage, HelloConnectionMessage, Opcode,
@@ -39,6 +43,10 @@ use crate::{web_socket, Encoding};
 pub struct WebRtcConnection {
     pub id: i64,
     pub last_heartbeat_at: Instant,
+    /// Last request of the client, heartbeats don't count as activity
+    pub last_activity_at: Instant,
+    pub is_idle: bool,
+    pub rate_limiter: RateLimiter,
     pub encoding: Encoding,
     pub registered_room_id: i64,
     pub registered_user_id: i64,
@@ -47,6 +55,10 @@ pub struct WebRtcConnection {
     #[educe(Debug(ignore))]
     pub data_channel_for_reader: Arc<Mutex<Option<Arc<DataChannel>>>>,
     pub data_channel_for_writer: Arc<Mutex<Option<Arc<DataChannel>>>>,
+    /// Unordered and without retransmits, for signals that are soon outdated anyway
+    #[educe(Debug(ignore))]
+    pub ephemeral_data_channel_for_reader: Arc<Mutex<Option<Arc<DataChannel>>>>,
+    pub ephemeral_data_channel_for_writer: Arc<Mutex<Option<Arc<DataChannel>>>>,
     pub is_closing_connection: bool,
 }
 
@@ -91,6 +103,9 @@ impl WebRtcConnection {
         Ok(Self {
             id,
             last_heartbeat_at: Instant::now(),
+            last_activity_at: Instant::now(),
+            is_idle: false,
+            rate_limiter: RateLimiter::default(),
             encoding,
             registered_room_id,
             registered_user_id,
@@ -98,6 +113,8 @@ impl WebRtcConnection {
             peer_connection,
             data_channel_for_reader: Arc::new(Mutex::new(None)),
             data_channel_for_writer: Arc::new(Mutex::new(None)),
+            ephemeral_data_channel_for_reader: Arc::new(Mutex::new(None)),
+            ephemeral_data_channel_for_writer: Arc::new(Mutex::new(None)),
             is_closing_connection: false,
         })
     }
@@ -106,35 +123,37 @@ impl WebRtcConnection {
         let peer_connection = self.peer_connection.clone();
         let data_channel_for_reader = self.data_channel_for_reader.clone();
         let data_channel_for_writer = self.data_channel_for_writer.clone();
+        let ephemeral_data_channel_for_reader = self.ephemeral_data_channel_for_reader.clone();
+        let ephemeral_data_channel_for_writer = self.ephemeral_data_channel_for_writer.clone();
+        let connection_id = self.id;
         let address = context.address();
 
         let init = async move {
-            let Ok(data_channel) = peer_connection.create_data_channel("", None).await else {
-                return Err(AppErrorTemplate::InternalServerError(None).into());
-            };
-            let data_channel_cloned = Arc::clone(&data_channel);
-
-            data_channel.on_open(Box::new(move || {
-                let data_channel_cloned = Arc::clone(&data_channel_cloned);
-                Box::pin(async move {
-                    // TODO: Better error handling
-                    let Ok(data_channel_detached) = data_channel_cloned.detach().await else {
-                        return;
-                    };
-
-                    if let Ok(mut data_channel) = data_channel_for_reader.lock() {
-                        *data_channel = Some(data_channel_detached.clone());
-                    }
-
-                    if let Ok(mut data_channel) = data_channel_for_writer.lock() {
-                        *data_channel = Some(data_channel_detached);
-                    }
-
+            Self::create_data_channel(
+                &peer_connection,
+                "",
+                None,
+                data_channel_for_reader,
+                data_channel_for_writer,
+                move |_| {
                     address.do_send(HelloConnectionMessage);
-                })
-            }));
-
-            Ok(())
+                    future::ready(())
+                },
+            )
+            .await?;
+            Self::create_data_channel(
+                &peer_connection,
+                WEB_RTC_EPHEMERAL_DATA_CHANNEL_LABEL,
+                Some(RTCDataChannelInit {
+                    ordered: Some(false),
+                    max_retransmits: Some(0),
+                    ..Default::default()
+                }),
+                ephemeral_data_channel_for_reader,
+                ephemeral_data_channel_for_writer,
+                move |data_channel| Self::drain(data_channel, connection_id),
+            )
+            .await
         };
 
         init.into_actor(self)
@@ -148,6 +167,50 @@ impl WebRtcConnection {
             .spawn(context);
     }
 
+    /// Creates a data channel, which is detached into both slots once it opens.
+    ///
+    /// `on_open` gets the detached channel and runs on a task of its own.
+    async fn create_data_channel<F>(
+        peer_connection: &RTCPeerConnection,
+        label: &str,
+        options: Option<RTCDataChannelInit>,
+        data_channel_for_reader: Arc<Mutex<Option<Arc<DataChannel>>>>,
+        data_channel_for_writer: Arc<Mutex<Option<Arc<DataChannel>>>>,
+        on_open: impl Fn(Arc<DataChannel>) -> F + Send + Sync + 'static,
+    ) -> Result<(), AppError>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let Ok(data_channel) = peer_connection.create_data_channel(label, options).await else {
+            return Err(AppErrorTemplate::InternalServerError(None).into());
+        };
+        let data_channel_cloned = Arc::clone(&data_channel);
+        let on_open = Arc::new(on_open);
+
+        data_channel.on_open(Box::new(move || {
+            let data_channel_cloned = Arc::clone(&data_channel_cloned);
+            let on_open = Arc::clone(&on_open);
+            Box::pin(async move {
+                // TODO: Better error handling
+                let Ok(data_channel_detached) = data_channel_cloned.detach().await else {
+                    return;
+                };
+
+                if let Ok(mut data_channel) = data_channel_for_reader.lock() {
+                    *data_channel = Some(data_channel_detached.clone());
+                }
+
+                if let Ok(mut data_channel) = data_channel_for_writer.lock() {
+                    *data_channel = Some(data_channel_detached.clone());
+                }
+
+                on_open(data_channel_detached).await;
+            })
+        }));
+
+        Ok(())
+    }
+
     async fn create_offer(
         peer_connection: Arc<RTCPeerConnection>,
     ) -> Result<RTCSessionDescription, AppError> {
@@ -169,10 +232,35 @@ impl WebRtcConnection {
         context.run_interval(WEB_RTC_HEARTBEAT_INTERVAL, |actor, context| {
             if Instant::now().duration_since(actor.last_heartbeat_at) > WEB_RTC_CLIENT_TIMEOUT {
                 context.address().do_send(CloseConnectionMessage);
+
+                return;
+            }
+
+            if !actor.is_idle
+                && Instant::now().duration_since(actor.last_activity_at) > WEB_RTC_IDLE_TIMEOUT
+            {
+                actor.set_idle(true);
             }
         });
     }
 
+    /// Keeps track of whether the client is in use, which decides if the user shows up as idle
+    pub fn set_idle(&mut self, is_idle: bool) {
+        if !is_idle {
+            self.last_activity_at = Instant::now();
+        }
+
+        if self.is_idle == is_idle {
+            return;
+        }
+
+        self.is_idle = is_idle;
+
+        if let Err(error) = User::set_connection_idle(&self.id, &self.registered_user_id, is_idle) {
+            error!("Failed to update idle connection {}: {error}", self.id);
+        }
+    }
+
     pub fn send_message(
         encoding: Encoding,
         message: WebRtcMessage,
@@ -182,13 +270,18 @@ impl WebRtcConnection {
         // Don't use the if statement to easily increase encodings.
         match encoding {
             Encoding::MessagePack => {
-                let data_channel = connection.data_channel_for_writer.clone();
+                let data_channel = match message.payload.is_ephemeral() {
+                    true => connection.ephemeral_data_channel_for_writer.clone(),
+                    false => connection.data_channel_for_writer.clone(),
+                };
 
                 async move {
-                    let Ok(data_channel) = data_channel.lock() else {
+                    let Ok(data_channel) =
+                        data_channel.lock().map(|data_channel| data_channel.clone())
+                    else {
                         return Ok(());
                     };
-                    let Some(ref data_channel) = *data_channel else {
+                    let Some(data_channel) = data_channel else {
                         return Ok(());
                     };
 
@@ -210,8 +303,35 @@ impl WebRtcConnection {
         Ok(())
     }
 
+    /// Reads requests from the reliable data channel, until one can't be read
+    async fn listen(data_channel: Arc<DataChannel>, connection_id: i64, address: Addr<Self>) {
+        while Self::receive_message(&data_channel, connection_id, address.clone())
+            .await
+            .is_ok()
+        {}
+    }
+
+    /// Discards what is sent over the ephemeral data channel, requests go over the reliable one.
+    ///
+    /// Read failures are only logged, as losing the ephemeral channel leaves the connection usable.
+    async fn drain(data_channel: Arc<DataChannel>, connection_id: i64) {
+        let mut buffer = vec![0u8; WEB_RTC_DATA_CHANNEL_BUFFER_SIZE];
+
+        loop {
+            match data_channel.read(&mut buffer).await {
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(error) => {
+                    warn!("Failed to read the ephemeral data channel of connection {connection_id}: {error}");
+
+                    return;
+                }
+            }
+        }
+    }
+
     async fn receive_message(
-        data_channel: Arc<DataChannel>,
+        data_channel: &DataChannel,
         connection_id: i64,
         address: Addr<Self>,
     ) -> Result<(), AppError> {
@@ -300,9 +420,6 @@ impl Handler<RtcAnswerConnectionMessage> for WebRtcConnection {
         context: &mut Self::Context,
     ) -> Self::Result {
         let peer_connection = Arc::clone(&self.peer_connection);
-        let data_channel = self.data_channel_for_reader.clone();
-        let connection_id = self.id;
-        let address = context.address();
         let Ok(sdp) = RTCSessionDescription::answer(message.sdp) else {
             return Err(AppErrorTemplate::BadRequest(None).into());
         };
@@ -318,33 +435,6 @@ impl Handler<RtcAnswerConnectionMessage> for WebRtcConnection {
             })
             .spawn(context);
 
-        let data_channel_listener = async move {
-            let mut interval = time::interval(Duration::from_nanos(1));
-
-            loop {
-                interval.tick().await;
-
-                let Ok(data_channel) = data_channel.lock() else {
-                    continue;
-                };
-                let Some(data_channel) = data_channel.clone() else {
-                    continue;
-                };
-
-                if Self::receive_message(data_channel, connection_id, address.clone())
-                    .await
-                    .is_err()
-                {
-                    return;
-                };
-            }
-        };
-
-        data_channel_listener
-            .into_actor(self)
-            .map(|_, _, context| context.address().do_send(CloseConnectionMessage))
-            .spawn(context);
-
         self.heartbeat(context);
 
         Ok(())
@@ -380,20 +470,41 @@ impl Handler<HelloConnectionMessage> for WebRtcConnection {
     type Result = Result<(), AppError>;
 
     fn handle(&mut self, _: HelloConnectionMessage, context: &mut Self::Context) -> Self::Result {
+        // The reliable data channel is in its slot once it opens, which is when the hello is sent
+        let data_channel = self
+            .data_channel_for_reader
+            .lock()
+            .ok()
+            .and_then(|data_channel| data_channel.clone());
+
+        if let Some(data_channel) = data_channel {
+            Self::listen(data_channel, self.id, context.address())
+                .into_actor(self)
+                .map(|_, _, context| context.address().do_send(CloseConnectionMessage))
+                .spawn(context);
+        }
+
+        let (messages, messages_cursor) = Message::find_page_by_room_id(
+            &self.registered_room_id,
+            None,
+            None,
+            MESSAGE_PAGE_DEFAULT_LIMIT,
+        )?;
+        let user = User::find_by_id(&self.registered_user_id)?;
         let message = WebRtcMessage {
             id: -1,
             connection_id: self.id,
             opcode: Opcode::Hello,
             payload: WebRtcMessagePayload::Hello {
                 user_id: self.registered_user_id.to_string(),
+                room: RoomPublic::from_room(Room::find_by_id(&self.registered_room_id)?, &user),
                 users: User::find_all_by_room_id(&self.registered_room_id)?
                     .iter()
                     .map(|user| user.clone().into())
                     .collect(),
-                messages: Message::find_all_by_room_id(&self.registered_room_id)?
-                    .iter()
-                    .map(|message| message.clone().into())
-                    .collect(),
+                messages: MessagePublic::from_messages(messages, &self.registered_user_id)?,
+                messages_cursor: messages_cursor.map(|cursor| cursor.to_string()),
+                unread_count: user.count_unread_messages()?,
             },
         };
         Self::send_message(self.encoding, message, self, context)
@@ -408,7 +519,25 @@ impl Handler<SendToServiceHandlerConnectionMessage> for WebRtcConnection {
         message: SendToServiceHandlerConnectionMessage,
         context: &mut Self::Context,
     ) -> Self::Result {
-        WebRtc::handle_message(self, message.message, context)
+        let id = message.message.id;
+        let connection_id = message.message.connection_id;
+
+        if let Err(error) = WebRtc::handle_message(self, message.message, context) {
+            let message = WebRtcMessage {
+                id,
+                connection_id,
+                opcode: Opcode::Error,
+                payload: WebRtcMessagePayload::Response {
+                    code: error.json_code,
+                    message: error.get_safe_message(),
+                    retry_after: error.retry_after,
+                },
+            };
+
+            Self::send_message(self.encoding, message, self, context)?;
+        }
+
+        Ok(())
     }
 }
 
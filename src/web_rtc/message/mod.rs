@@ -3,14 +3,16 @@ use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::error::AppError;
+use crate::services::direct_message::model::DirectMessage;
 use crate::services::message::model;
+use crate::services::room::model::Room;
 use crate::services::user::model::User;
 use crate::web_rtc::connection::WebRtcConnection;
 pub use crate::web_rtc::message::payload::*;
 
 mod payload;
 
-#[derive(Debug, Default, Deserialize_repr, Serialize_repr, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize_repr, Serialize_repr, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Opcode {
     HeartBeat = 0,
@@ -53,6 +55,27 @@ pub struct UserUpdateMessage {
     pub room_id: i64,
 }
 
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct ReadReceiptMessage {
+    pub user: User,
+    pub room_id: i64,
+}
+
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct TypingMessage {
+    pub user_id: i64,
+    pub room_id: i64,
+    pub is_typing: bool,
+}
+
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct RoomUpdateMessage {
+    pub room: Room,
+}
+
 #[derive(Debug, Message)]
 #[rtype(result = "Result<(), AppError>")]
 pub struct MessageUpdateMessage {
@@ -60,6 +83,51 @@ pub struct MessageUpdateMessage {
     pub room_id: i64,
 }
 
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct MessageDeleteMessage {
+    pub message: model::Message,
+    pub room_id: i64,
+}
+
+/// Goes only to the connections of the mentioned users
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct MessageMentionMessage {
+    pub message: model::Message,
+    pub user_ids: Vec<i64>,
+}
+
+/// Goes only to the connections of the author and the recipient
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct DirectMessageUpdateMessage {
+    pub message: DirectMessage,
+}
+
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct MessageReactionUpdateMessage {
+    pub message_id: i64,
+    pub reactions: Vec<model::MessageReaction>,
+    pub room_id: i64,
+}
+
+/// Lifts the mute of a user once it is over
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct UserMuteMessage {
+    pub user_id: i64,
+    pub muted_until: i64,
+}
+
+/// Closes connections of a user who was removed from their room
+#[derive(Debug, Message)]
+#[rtype(result = "Result<(), AppError>")]
+pub struct UserKickMessage {
+    pub connection_ids: Vec<i64>,
+}
+
 #[derive(Debug, Message)]
 #[rtype(result = "Result<(), AppError>")]
 pub struct DisconnectionMessage {
@@ -72,6 +140,7 @@ pub struct RtcAnswerConnectionMessage {
     pub sdp: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Message)]
 #[rtype(result = "Result<(), AppError>")]
 pub struct RtcCandidateConnectionMessage {
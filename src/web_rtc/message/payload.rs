@@ -5,17 +5,112 @@ use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::payload_enum_helper;
-use crate::services::message::model::MessagePublic;
-use crate::services::user::model::UserPublic;
+use crate::services::direct_message::model::DirectMessagePublic;
+use crate::services::message::filter::MessageFilterRule;
+use crate::services::message::model::{
+    MessagePublic, MessageReactionPublic, MessageRevisionPublic,
+};
+use crate::services::room::model::RoomPublic;
+use crate::services::user::model::{UserPublic, UserRole, UserStatus};
 
 payload_enum_helper! {
     #[derive(Clone, Debug, Default)]
     enum WebRtcMessagePayload {
         // Opcode: Request
-        RequestPostMessage { content: String, } = "10" | 10,
+        RequestPostMessage {
+            content: String,
+            reply_to_id: Option<String>,
+        } = "10" | 10,
+        RequestGetMessages {
+            before: Option<String>,
+            after: Option<String>,
+            limit: Option<usize>,
+        } = "11" | 11,
+        RequestPatchRoomRetention {
+            message_max_age: Option<u64>,
+            message_max_count: Option<u64>,
+        } = "12" | 12,
+        RequestGetMessageSearch {
+            query: String,
+            author_id: Option<String>,
+            after: Option<i64>,
+            before: Option<i64>,
+            cursor: Option<String>,
+            limit: Option<usize>,
+        } = "13" | 13,
+        RequestPatchMessage {
+            message_id: String,
+            content: String,
+        } = "14" | 14,
+        RequestGetMessageRevisions { message_id: String, } = "15" | 15,
+        RequestDeleteMessage {
+            message_id: String,
+            reason: Option<String>,
+        } = "16" | 16,
+        RequestPutMessageReaction {
+            message_id: String,
+            emoji: String,
+        } = "17" | 17,
+        RequestDeleteMessageReaction {
+            message_id: String,
+            emoji: String,
+        } = "18" | 18,
+        RequestGetThreadMessages {
+            message_id: String,
+            before: Option<String>,
+            after: Option<String>,
+            limit: Option<usize>,
+        } = "19" | 19,
+        RequestPatchReadReceipt { message_id: String, } = "110" | 110,
+        RequestPutTyping = "111" | 111,
+        RequestDeleteTyping = "112" | 112,
+        RequestPatchUserStatus {
+            status: UserStatus,
+            status_text: Option<String>,
+        } = "113" | 113,
+        RequestPostDirectMessage {
+            user_id: String,
+            content: String,
+        } = "114" | 114,
+        RequestGetDirectMessages {
+            user_id: String,
+            before: Option<String>,
+            after: Option<String>,
+            limit: Option<usize>,
+        } = "115" | 115,
+        RequestPostRoomInvite = "116" | 116,
+        RequestPatchUserRole {
+            user_id: String,
+            role: UserRole,
+        } = "117" | 117,
+        RequestPostRoomKick { user_id: String, } = "118" | 118,
+        RequestPutRoomBan { user_id: String, } = "119" | 119,
+        RequestPutUserMute { user_id: String, duration: u64, } = "120" | 120,
+        RequestDeleteUserMute { user_id: String, } = "121" | 121,
+        RequestPatchRoomSlowMode { slow_mode_interval: Option<u64>, } = "122" | 122,
+        RequestPatchRoomMessageFilters { message_filters: Vec<MessageFilterRule>, } = "123" | 123,
 
         // Opcode: Response
-        Response { code: u32, message: String, } = "20" | 20,
+        Response {
+            code: u32,
+            message: String,
+            retry_after: Option<u64>,
+        } = "20" | 20,
+        ResponseMessages {
+            messages: Vec<MessagePublic>,
+            cursor: Option<String>,
+        } = "21" | 21,
+        ResponseMessageRevisions {
+            revisions: Vec<MessageRevisionPublic>,
+        } = "22" | 22,
+        ResponseDirectMessages {
+            messages: Vec<DirectMessagePublic>,
+            cursor: Option<String>,
+        } = "23" | 23,
+        ResponseRoomInvite {
+            code: String,
+            expires_at: i64,
+        } = "24" | 24,
 
         // Opcode: Dispatch
         DispatchUserUpdate {
@@ -24,12 +119,39 @@ payload_enum_helper! {
         DispatchMessageUpdate {
             message: MessagePublic,
         } = "41" | 41,
+        DispatchRoomUpdate {
+            room: RoomPublic,
+        } = "42" | 42,
+        DispatchMessageDelete {
+            message: MessagePublic,
+        } = "43" | 43,
+        DispatchMessageReactionUpdate {
+            message_id: String,
+            reactions: Vec<MessageReactionPublic>,
+        } = "44" | 44,
+        DispatchMessageMention {
+            message: MessagePublic,
+        } = "45" | 45,
+        DispatchReadReceipt {
+            user_id: String,
+            message_id: String,
+        } = "46" | 46,
+        DispatchTypingUpdate {
+            user_id: String,
+            is_typing: bool,
+        } = "47" | 47,
+        DispatchDirectMessageUpdate {
+            message: DirectMessagePublic,
+        } = "48" | 48,
 
         // Opcode: Hello
         Hello {
             user_id: String,
+            room: RoomPublic,
             users: Vec<UserPublic>,
             messages: Vec<MessagePublic>,
+            messages_cursor: Option<String>,
+            unread_count: usize,
         } = "50" | 50,
 
         // Other
@@ -42,6 +164,11 @@ impl WebRtcMessagePayload {
     pub fn is_none(&self) -> bool {
         matches!(self, WebRtcMessagePayload::None)
     }
+
+    /// Goes over the ephemeral data channel, where it may get lost
+    pub fn is_ephemeral(&self) -> bool {
+        matches!(self, WebRtcMessagePayload::DispatchTypingUpdate { .. })
+    }
 }
 
 impl<'de> Deserialize<'de> for WebRtcMessagePayload {
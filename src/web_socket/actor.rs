@@ -8,15 +8,28 @@ use actix::{
     SystemService, WrapFuture,
 };
 use actix_web_actors::ws::WebsocketContext;
+use lazy_static::lazy_static;
 
+use crate::constants::WEB_SOCKET_RATE_LIMITS;
 use crate::error::{AppError, AppErrorTemplate, WebSocketCloseError};
 use crate::services::room;
 use crate::services::session::model::Session;
+use crate::utils::rate_limiter::RateLimits;
 use crate::web_socket::connection::WebSocketConnection;
 use crate::web_socket::message::{
     AuthorizationMessage, DisconnectionMessage, Opcode, WebSocketMessage, WebSocketMessagePayload,
 };
 
+lazy_static! {
+    static ref RATE_LIMITS: RateLimits =
+        RateLimits::from_env("MESSENGER_WEB_SOCKET_RATE_LIMITS", WEB_SOCKET_RATE_LIMITS);
+}
+
+/// Reads the rate limits up front, so that an invalid configuration stops the startup
+pub fn init_rate_limits() {
+    lazy_static::initialize(&RATE_LIMITS);
+}
+
 #[derive(Debug, Default)]
 pub struct WebSocket {
     connections: HashMap<i64, Addr<WebSocketConnection>>,
@@ -63,6 +76,13 @@ impl WebSocket {
             return Ok(());
         }
 
+        // Limits apply to identified connections, which get one authorization only
+        connection.rate_limiter.check(
+            &RATE_LIMITS,
+            message.opcode as u8,
+            message.payload.get_type(),
+        )?;
+
         match message.opcode {
             Opcode::HeartBeat => {
                 connection.last_heartbeat_at = Instant::now();
@@ -126,6 +146,7 @@ impl WebSocket {
                         payload: WebSocketMessagePayload::Response {
                             code: error.json_code,
                             message: error.get_safe_message(),
+                            retry_after: error.retry_after,
                         },
                     });
                 }
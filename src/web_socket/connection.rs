@@ -11,6 +11,7 @@ use rmp_serde::decode::Error as RmpSerdeDecodeError;
 use crate::constants::{WEB_SOCKET_CLIENT_TIMEOUT, WEB_SOCKET_HEARTBEAT_INTERVAL};
 use crate::error::{AppError, WebSocketCloseError};
 use crate::services::room::model::Room;
+use crate::utils::rate_limiter::RateLimiter;
 use crate::utils::snowflake_generator;
 use crate::web_rtc::connection::WebRtcConnection;
 use crate::web_socket::actor::WebSocket;
@@ -28,6 +29,7 @@ pub struct WebSocketConnection {
     pub registered_room_id: Option<i64>,
     pub registered_user_id: Option<i64>,
     pub web_rtc_connection: Arc<Mutex<Option<Addr<WebRtcConnection>>>>,
+    pub rate_limiter: RateLimiter,
 }
 
 impl WebSocketConnection {
@@ -40,6 +42,7 @@ impl WebSocketConnection {
             registered_room_id: None,
             registered_user_id: None,
             web_rtc_connection: Arc::new(Mutex::new(None)),
+            rate_limiter: RateLimiter::default(),
         }
     }
 
@@ -93,6 +96,7 @@ impl WebSocketConnection {
                     payload: WebSocketMessagePayload::Response {
                         code: error.json_code,
                         message: error.get_safe_message(),
+                        retry_after: error.retry_after,
                     },
                 },
                 context,
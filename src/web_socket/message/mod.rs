@@ -8,7 +8,7 @@ pub use crate::web_socket::message::payload::*;
 
 mod payload;
 
-#[derive(Debug, Default, Deserialize_repr, Serialize_repr, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize_repr, Serialize_repr, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Opcode {
     HeartBeat = 0,
@@ -19,6 +19,7 @@ pub enum Opcode {
     Authorize = 4,
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Method {
@@ -56,6 +57,7 @@ pub struct AuthorizationMessage {
 #[rtype(result = "Result<(), AppError>")]
 pub struct DisconnectionMessage {
     pub connection_id: i64,
+    #[allow(dead_code)]
     pub registered_room_id: Option<i64>,
 }
 
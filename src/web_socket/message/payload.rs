@@ -5,12 +5,20 @@ use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::payload_enum_helper;
+use crate::services::room::model::{RoomAccess, RoomMode};
 
 payload_enum_helper! {
     #[derive(Clone, Debug, Default)]
     enum WebSocketMessagePayload {
         // Opcode: Request
-        RequestGetRoomSdpOffer { room_name: String, username: String, } = "10" | 10,
+        RequestGetRoomSdpOffer {
+            room_name: String,
+            username: String,
+            room_mode: Option<RoomMode>,
+            room_access: Option<RoomAccess>,
+            password: Option<String>,
+            invite_code: Option<String>,
+        } = "10" | 10,
         RequestPostRoomSdpAnswer { sdp: String, } = "11" | 11,
         RequestPostRoomIceCandidate {
             candidate: String,
@@ -20,7 +28,11 @@ payload_enum_helper! {
         } = "12" | 12, // Reserved
 
         // Opcode: Response
-        Response { code: u32, message: String, } = "20" | 20,
+        Response {
+            code: u32,
+            message: String,
+            retry_after: Option<u64>,
+        } = "20" | 20,
         ResponseSession { token: String, } = "21" | 21,
         ResponseRoomRtcOffer { sdp: String, } = "22" | 22,
 
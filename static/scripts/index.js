@@ -5,6 +5,9 @@ const appViewStates = {
 const userStatuses = {
     offline: 0,
     online: 1,
+    idle: 2,
+    away: 3,
+    doNotDisturb: 4,
 }
 
 const dataStore = {
@@ -47,6 +50,7 @@ const systemStore = {
 
     webRTCPeerConnection: null,
     webRTCDataChannel: null,
+    webRTCEphemeralDataChannel: null,
     webRTCLastMessageId: 0,
     webRTCTimeout: null,
     webRTCPing: null,
@@ -89,6 +93,10 @@ const systemStore = {
                 this.webRTCDataChannel.close()
                 this.webRTCDataChannel = null
             }
+            if (this.webRTCEphemeralDataChannel) {
+                this.webRTCEphemeralDataChannel.close()
+                this.webRTCEphemeralDataChannel = null
+            }
 
             clearTimeout(this.webRTCTimeout)
             clearInterval(this.webRTCHeartbeatInterval)
@@ -109,6 +117,13 @@ const systemStore = {
             })
 
             this.webRTCPeerConnection.ondatachannel = event => {
+                // Typing indicators and other signals that may get lost come through here
+                if (event.channel.label === webRTCEphemeralDataChannelLabel) {
+                    this.webRTCEphemeralDataChannel = event.channel
+
+                    return
+                }
+
                 const reconnect = () => {
                     if (this.isWebRTCForceClosing) return
 
@@ -132,7 +147,10 @@ const systemStore = {
                             Alpine.store('data').users.set(payload.user.id, {
                                 id: payload.user.id,
                                 username: payload.user.username,
+                                role: payload.user.role,
                                 status: payload.user.status,
+                                statusText: payload.user['status_text'],
+                                mutedUntil: payload.user['muted_until'],
                             })
 
                             break
@@ -151,7 +169,10 @@ const systemStore = {
                                 Alpine.store('data').users.set(user.id, {
                                     id: user.id,
                                     username: user.username,
+                                    role: user.role,
                                     status: user.status,
+                                    statusText: user['status_text'],
+                                    mutedUntil: user['muted_until'],
                                 })
                             }
 
@@ -302,6 +323,11 @@ const systemStore = {
                                     error: 'This username is already taken in selected room',
                                 })
                                 break
+                            case serverErrors.userBanned:
+                                setError({
+                                    error: 'You are banned from this room',
+                                })
+                                break
                         }
 
                         break
@@ -454,7 +480,7 @@ const rootData = {
 
         this.$store.data.users.forEach(user => {
             if (
-                user.status === userStatuses.online &&
+                user.status !== userStatuses.offline &&
                 user.id !== this.$store.data.userId
             )
                 users.push(user)
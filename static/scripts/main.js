@@ -30,6 +30,7 @@ const webSocketCloseErrors = {
     authenticationFailed: 4004,
     alreadyAuthenticated: 4005,
 }
+const webRTCEphemeralDataChannelLabel = 'ephemeral'
 const webRTCOpcodes = {
     heartBeat: 0,
     request: 1,
@@ -41,13 +42,47 @@ const webRTCOpcodes = {
 const webRTCPayloadTypes = {
     // Request
     requestPostMessage: 10,
+    requestGetMessages: 11,
+    requestPatchRoomRetention: 12,
+    requestGetMessageSearch: 13,
+    requestPatchMessage: 14,
+    requestGetMessageRevisions: 15,
+    requestDeleteMessage: 16,
+    requestPutMessageReaction: 17,
+    requestDeleteMessageReaction: 18,
+    requestGetThreadMessages: 19,
+    requestPatchReadReceipt: 110,
+    requestPutTyping: 111,
+    requestDeleteTyping: 112,
+    requestPatchUserStatus: 113,
+    requestPostDirectMessage: 114,
+    requestGetDirectMessages: 115,
+    requestPostRoomInvite: 116,
+    requestPatchUserRole: 117,
+    requestPostRoomKick: 118,
+    requestPutRoomBan: 119,
+    requestPutUserMute: 120,
+    requestDeleteUserMute: 121,
+    requestPatchRoomSlowMode: 122,
+    requestPatchRoomMessageFilters: 123,
 
     // Response
     response: 20,
+    responseMessages: 21,
+    responseMessageRevisions: 22,
+    responseDirectMessages: 23,
+    responseRoomInvite: 24,
 
     // Dispatch
     dispatchUserUpdate: 40,
     dispatchMessageUpdate: 41,
+    dispatchRoomUpdate: 42,
+    dispatchMessageDelete: 43,
+    dispatchMessageReactionUpdate: 44,
+    dispatchMessageMention: 45,
+    dispatchReadReceipt: 46,
+    dispatchTypingUpdate: 47,
+    dispatchDirectMessageUpdate: 48,
 
     // Hello
     hello: 50,
@@ -72,9 +107,23 @@ const serverErrors = {
     usernameTooLong: 3004,
     messageContentTooShort: 3005,
     messageContentTooLong: 3006,
+    messageDeletionReasonTooLong: 3007,
+    messageReactionEmojiTooShort: 3008,
+    messageReactionEmojiTooLong: 3009,
+    userStatusTextTooLong: 3010,
+    roomPasswordTooShort: 3011,
+    roomPasswordTooLong: 3012,
 
     // Invalid body or something else
     usernameTaken: 4001,
+    messageReplyInAnotherRoom: 4004,
+    roomPasswordInvalid: 4005,
+    roomInviteInvalid: 4006,
+    userBanned: 4007,
+    userMuted: 4008,
+    rateLimited: 4009,
+    roomSlowMode: 4010,
+    messageContentBlocked: 4011,
 }
 
 // Boring Avatars